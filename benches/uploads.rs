@@ -0,0 +1,52 @@
+// Compares uploading the display to the backend after every draw with
+// uploading it once a frame, on a rom that does nothing but draw.
+//
+// cargo bench --bench uploads
+
+#![allow(clippy::needless_return)]
+
+use std::time::{ Duration, Instant };
+
+use chip8_emulator::*;
+use chip8_emulator::render::{ composite, Palette };
+
+const FRAMES: u32 = 2000;
+const CYCLES_PER_FRAME: u32 = 300;
+
+// Draws the sprite at 0x208 across the screen forever, one Dxyn every
+// three instructions.
+const DRAW_HEAVY: [u8; 13] = [0xA2, 0x08, 0xD0, 0x15, 0x70, 0x08, 0x12, 0x02, 0xF0, 0x90, 0xF0, 0x90, 0xF0];
+
+// Stands in for a texture upload: the display converted to pixels.
+fn upload(display_memory: &[bool], palette: &Palette, uploads: &mut u32) {
+    let pixels = composite(display_memory, &[], palette);
+    assert_eq!(pixels.len(), display_memory.len());
+    *uploads += 1;
+}
+
+fn run(per_draw: bool) -> (Duration, u32) {
+    let mut chip8 = Chip8Builder::new().seed(0).build();
+    chip8.load_rom_bytes(&DRAW_HEAVY).unwrap();
+    let palette = Palette::default();
+    let mut uploads = 0;
+    let start = Instant::now();
+    for _ in 0..FRAMES {
+        for _ in 0..CYCLES_PER_FRAME {
+            chip8.step().unwrap();
+            if per_draw {
+                chip8.on_draw(|display| upload(display, &palette, &mut uploads));
+            }
+        }
+        chip8.tick_timers();
+        chip8.on_draw(|display| upload(display, &palette, &mut uploads));
+    }
+    return (start.elapsed(), uploads);
+}
+
+fn main() {
+    for (name, per_draw) in [("per draw", true), ("per frame", false)] {
+        let (elapsed, uploads) = run(per_draw);
+        println!("{:>9}: {:>7} uploads in {:?}, {:.1}us a frame",
+            name, uploads, elapsed, elapsed.as_secs_f64() * 1e6 / FRAMES as f64);
+    }
+}
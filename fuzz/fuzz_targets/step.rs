@@ -0,0 +1,41 @@
+// Runs arbitrary bytes as a rom from arbitrary registers, I and PC,
+// checking that step never panics and I stays inside memory. Needs a nightly toolchain:
+//
+//     cargo install cargo-fuzz
+//     cargo +nightly fuzz run step
+//
+// The first 20 bytes are V0..VF, I and PC, the rest is the rom.
+#![no_main]
+
+use libfuzzer_sys::fuzz_target;
+
+use chip8_emulator::*;
+
+fuzz_target!(|data: &[u8]| {
+    if data.len() < 20 {
+        return;
+    }
+    let (state, rom) = data.split_at(20);
+    let quirks = Quirks {
+        shift : state[0] & 1 != 0,
+        load_store : state[0] & 2 != 0,
+        schip : state[0] & 4 != 0,
+        xochip : state[0] & 8 != 0,
+        .. Quirks::default()
+    };
+    let chip8 = Chip8Builder::new().quirks(quirks).seed(0).build();
+    let mut chip8 = match load_rom_bytes(chip8, rom) {
+        Ok(chip8) => chip8,
+        Err(_) => return,
+    };
+    chip8.v.copy_from_slice(&state[..16]);
+    chip8.set_i((state[16] as u16) << 8 | state[17] as u16);
+    chip8.program_counter = (state[18] as u16) << 8 | state[19] as u16;
+    for _ in 0..1000 {
+        chip8 = match step(chip8) {
+            Ok(chip8) => chip8,
+            Err(_) => return,
+        };
+        assert!((chip8.i() as usize) < chip8.memory.len());
+    }
+});
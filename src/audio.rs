@@ -0,0 +1,162 @@
+use std::f32::consts::PI;
+use std::sync::{Arc, Mutex};
+
+use crate::shutdown::Shutdown;
+use crate::Chip8;
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Waveform {
+    Square,
+    Triangle,
+    Sine,
+}
+
+#[derive(Clone, Copy, Debug)]
+pub struct AudioConfig {
+    pub waveform: Waveform,
+    pub frequency: f32,
+    pub volume: f32,
+}
+
+impl Default for AudioConfig {
+    fn default() -> AudioConfig {
+        return AudioConfig { waveform : Waveform::Square, frequency : 440.0, volume : 0.25 };
+    }
+}
+
+// `phase` is the position within one period, in [0, 1).
+pub fn waveform_sample(waveform: Waveform, phase: f32) -> f32 {
+    return match waveform {
+        Waveform::Square => if phase < 0.5 { 1.0 } else { -1.0 },
+        Waveform::Triangle => 4.0 * ((phase - 0.25).rem_euclid(1.0) - 0.5).abs() - 1.0,
+        Waveform::Sine => (2.0 * PI * phase).sin(),
+    };
+}
+
+// XO-CHIP plays the 128-bit pattern buffer, most significant bit first,
+// at 4000 * 2^((pitch - 64) / 48) bits per second.
+pub fn playback_rate(pitch: u8) -> f32 {
+    return 4000.0 * 2f32.powf((pitch as f32 - 64.0) / 48.0);
+}
+
+pub fn pattern_sample(pattern: &[u8; 16], bit: usize) -> f32 {
+    let byte = pattern[(bit / 8) % 16];
+    return if byte & (0x80 >> (bit % 8)) != 0 { 1.0 } else { -1.0 };
+}
+
+// Sample generator for audio backends. Keeps the phase between buffers
+// so the tone stays continuous. In XO-CHIP mode the pattern buffer
+// replaces the configured waveform.
+pub struct Beeper {
+    config: AudioConfig,
+    sample_rate: u32,
+    phase: f32,
+    stopped: bool,
+}
+
+impl Beeper {
+    pub fn new(config: AudioConfig, sample_rate: u32) -> Beeper {
+        return Beeper { config, sample_rate, phase : 0.0, stopped : false };
+    }
+
+    // Silences the beeper for good, so a tone playing at exit doesn't
+    // linger while the backend winds down.
+    pub fn stop(&mut self) {
+        self.stopped = true;
+    }
+
+    pub fn fill(&mut self, chip8: &Chip8, out: &mut [f32]) {
+        if chip8.sound_timer == 0 || self.stopped {
+            self.phase = 0.0;
+            out.iter_mut().for_each(|s| *s = 0.0);
+            return;
+        }
+        if chip8.quirks.xochip {
+            // Here the phase counts bits through the whole pattern.
+            let step = playback_rate(chip8.pitch) / self.sample_rate as f32;
+            for s in out.iter_mut() {
+                *s = self.config.volume * pattern_sample(&chip8.pattern_buffer, self.phase as usize);
+                self.phase = (self.phase + step) % 128.0;
+            }
+            return;
+        }
+        let step = self.config.frequency / self.sample_rate as f32;
+        for s in out.iter_mut() {
+            *s = self.config.volume * waveform_sample(self.config.waveform, self.phase);
+            self.phase = (self.phase + step).fract();
+        }
+    }
+}
+
+// Stops a beeper the backend shares with its audio callback when
+// `shutdown` runs.
+pub fn stop_on_shutdown(beeper: &Arc<Mutex<Beeper>>, shutdown: &mut Shutdown) {
+    let beeper = Arc::clone(beeper);
+    shutdown.on_shutdown(move || {
+        if let Ok(mut beeper) = beeper.lock() {
+            beeper.stop();
+        }
+    });
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Chip8Builder;
+
+    // One period is 8 samples, so the samples step an eighth of the
+    // way through the waveform each.
+    fn first_samples(waveform: Waveform) -> Vec<f32> {
+        let mut chip8 = Chip8Builder::new().build();
+        chip8.sound_timer = 1;
+        let mut beeper = Beeper::new(AudioConfig { waveform, frequency : 1.0, volume : 1.0 }, 8);
+        let mut out = [0.0; 5];
+        beeper.fill(&chip8, &mut out);
+        return out.to_vec();
+    }
+
+    fn assert_close(actual: &[f32], expected: &[f32]) {
+        assert_eq!(actual.len(), expected.len());
+        for (a, e) in actual.iter().zip(expected) {
+            assert!((a - e).abs() < 1e-4, "{:?} != {:?}", actual, expected);
+        }
+    }
+
+    #[test]
+    fn square_samples() {
+        assert_close(&first_samples(Waveform::Square), &[1.0, 1.0, 1.0, 1.0, -1.0]);
+    }
+
+    #[test]
+    fn triangle_samples() {
+        assert_close(&first_samples(Waveform::Triangle), &[0.0, 0.5, 1.0, 0.5, 0.0]);
+    }
+
+    #[test]
+    fn sine_samples() {
+        let half = 0.5f32.sqrt();
+        assert_close(&first_samples(Waveform::Sine), &[0.0, half, 1.0, half, 0.0]);
+    }
+
+    #[test]
+    fn silent_without_the_sound_timer() {
+        let chip8 = Chip8Builder::new().build();
+        let mut beeper = Beeper::new(AudioConfig::default(), 8);
+        let mut out = [1.0; 4];
+        beeper.fill(&chip8, &mut out);
+        assert_eq!(out, [0.0; 4]);
+    }
+
+    #[test]
+    fn shutdown_stops_the_beeper() {
+        let mut chip8 = Chip8Builder::new().build();
+        chip8.sound_timer = 1;
+        let beeper = Arc::new(Mutex::new(Beeper::new(AudioConfig::default(), 8)));
+        let mut shutdown = Shutdown::new();
+        stop_on_shutdown(&beeper, &mut shutdown);
+        shutdown.run();
+        let mut out = [1.0; 4];
+        beeper.lock().unwrap().fill(&chip8, &mut out);
+        assert_eq!(out, [0.0; 4]);
+    }
+}
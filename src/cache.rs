@@ -0,0 +1,83 @@
+use std::collections::VecDeque;
+use std::fs;
+
+use crate::{ Chip8, Chip8Error, ResetKind, load_rom_bytes, reset };
+
+// Keeps the last few roms read from disk, so switching between games
+// only re-splices bytes into a reset machine.
+pub struct RomCache {
+    capacity: usize,
+    // Least recently used first.
+    roms: VecDeque<(String, Vec<u8>)>,
+    hits: usize,
+}
+
+impl RomCache {
+    pub fn new(capacity: usize) -> RomCache {
+        return RomCache { capacity, roms : VecDeque::new(), hits : 0 };
+    }
+
+    pub fn hits(&self) -> usize {
+        return self.hits;
+    }
+
+    // Loads `path` into a reset copy of `chip8`. Roms that don't fit
+    // aren't cached.
+    pub fn load(&mut self, chip8: Chip8, path: &str) -> Result<Chip8, Chip8Error> {
+        let rom = match self.roms.iter().position(|(cached, _)| cached == path) {
+            Some(i) => {
+                self.hits += 1;
+                self.roms.remove(i).expect("The position was just found.").1
+            },
+            None => fs::read(path)?,
+        };
+        let loaded = load_rom_bytes(reset(chip8, ResetKind::HardClearAll), &rom)?;
+        if self.capacity > 0 {
+            if self.roms.len() == self.capacity {
+                self.roms.pop_front();
+            }
+            self.roms.push_back((path.to_string(), rom));
+        }
+        return Ok(loaded);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Chip8Builder;
+
+    fn temp_rom(name: &str, rom: &[u8]) -> String {
+        let path = std::env::temp_dir().join(format!("chip8_cache_{}_{}.ch8", std::process::id(), name));
+        fs::write(&path, rom).unwrap();
+        return path.to_str().unwrap().to_string();
+    }
+
+    #[test]
+    fn second_load_comes_from_the_cache() {
+        let path = temp_rom("twice", &[0x60, 0x07]);
+        let mut cache = RomCache::new(2);
+        cache.load(Chip8Builder::new().build(), &path).unwrap();
+        assert_eq!(cache.hits(), 0);
+        fs::remove_file(&path).unwrap();
+        let chip8 = cache.load(Chip8Builder::new().build(), &path).unwrap();
+        assert_eq!(cache.hits(), 1);
+        assert_eq!(&chip8.memory[0x200..0x202], &[0x60, 0x07]);
+    }
+
+    #[test]
+    fn least_recently_used_rom_is_dropped() {
+        let paths = ["a", "b", "c"].iter().map(|name| temp_rom(name, &[0x12, 0x00])).collect::<Vec<String>>();
+        let mut cache = RomCache::new(2);
+        for path in &paths {
+            cache.load(Chip8Builder::new().build(), path).unwrap();
+        }
+        cache.load(Chip8Builder::new().build(), &paths[2]).unwrap();
+        assert_eq!(cache.hits(), 1);
+        fs::remove_file(&paths[0]).unwrap();
+        assert!(cache.load(Chip8Builder::new().build(), &paths[0]).is_err());
+        for path in &paths[1..] {
+            fs::remove_file(path).unwrap();
+        }
+    }
+}
@@ -0,0 +1,67 @@
+use crate::{ Chip8, screen_text };
+
+pub trait Clipboard {
+    fn set_text(&mut self, text: String) -> Result<(), String>;
+}
+
+// Copies the screen as text, for pasting into chats and bug reports.
+pub fn copy_screen(chip8: &Chip8, clipboard: &mut impl Clipboard) -> Result<(), String> {
+    return clipboard.set_text(screen_text(&chip8.display_memory));
+}
+
+#[cfg(feature = "clipboard")]
+pub use self::system_clipboard::SystemClipboard;
+
+#[cfg(feature = "clipboard")]
+mod system_clipboard {
+    use super::Clipboard;
+
+    pub struct SystemClipboard {
+        clipboard: arboard::Clipboard,
+    }
+
+    impl SystemClipboard {
+        pub fn new() -> Result<SystemClipboard, String> {
+            let clipboard = arboard::Clipboard::new().map_err(|e| e.to_string())?;
+            return Ok(SystemClipboard { clipboard });
+        }
+    }
+
+    impl Clipboard for SystemClipboard {
+        fn set_text(&mut self, text: String) -> Result<(), String> {
+            return self.clipboard.set_text(text).map_err(|e| e.to_string());
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Chip8Builder;
+
+    #[derive(Default)]
+    struct MockClipboard {
+        text: Option<String>,
+    }
+
+    impl Clipboard for MockClipboard {
+        fn set_text(&mut self, text: String) -> Result<(), String> {
+            self.text = Some(text);
+            return Ok(());
+        }
+    }
+
+    #[test]
+    fn copies_the_screen_as_text() {
+        let mut chip8 = Chip8Builder::new().build();
+        let mut pixels = vec![false; 64 * 32];
+        pixels[0] = true;
+        pixels[64 + 63] = true;
+        chip8.set_display(&pixels).unwrap();
+        let mut clipboard = MockClipboard::default();
+        copy_screen(&chip8, &mut clipboard).unwrap();
+        let blank = " ".repeat(64) + "\n";
+        let expected = format!("#{}\n{}#\n{}", " ".repeat(63), " ".repeat(63), blank.repeat(30));
+        assert_eq!(clipboard.text, Some(expected));
+    }
+}
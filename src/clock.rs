@@ -0,0 +1,94 @@
+use std::time::{ Duration, Instant };
+
+use crate::Chip8;
+
+// Where the 60Hz timer ticks come from. Tests use a ManualClock so
+// the timers only move when the test says so.
+pub trait Clock {
+    fn elapsed(&self) -> Duration;
+}
+
+pub struct SystemClock {
+    start: Instant,
+}
+
+impl SystemClock {
+    pub fn new() -> SystemClock {
+        return SystemClock { start : Instant::now() };
+    }
+}
+
+impl Default for SystemClock {
+    fn default() -> SystemClock {
+        return SystemClock::new();
+    }
+}
+
+impl Clock for SystemClock {
+    fn elapsed(&self) -> Duration {
+        return self.start.elapsed();
+    }
+}
+
+#[derive(Default)]
+pub struct ManualClock {
+    now: Duration,
+}
+
+impl ManualClock {
+    pub fn new() -> ManualClock {
+        return ManualClock { now : Duration::from_secs(0) };
+    }
+
+    pub fn advance(&mut self, by: Duration) {
+        self.now += by;
+    }
+}
+
+impl Clock for ManualClock {
+    fn elapsed(&self) -> Duration {
+        return self.now;
+    }
+}
+
+// Ticks a machine's timers once for every 1/60 s its clock has moved on.
+#[derive(Default)]
+pub struct TimerTicker {
+    ticked: u64,
+}
+
+impl TimerTicker {
+    pub fn new() -> TimerTicker {
+        return TimerTicker { ticked : 0 };
+    }
+
+    pub fn update(&mut self, clock: &impl Clock, chip8: &mut Chip8) {
+        let due = (clock.elapsed().as_micros() * 60 / 1_000_000) as u64;
+        while self.ticked < due {
+            chip8.tick_timers();
+            self.ticked += 1;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Chip8Builder;
+
+    #[test]
+    fn delay_timer_reads_back_until_the_clock_moves() {
+        let mut chip8 = Chip8Builder::new().build();
+        chip8.load_rom_bytes(&[0x60, 0x2A, 0xF0, 0x15, 0xF1, 0x07]).unwrap();
+        let mut clock = ManualClock::new();
+        let mut ticker = TimerTicker::new();
+        for _ in 0..3 {
+            chip8.step().unwrap();
+            ticker.update(&clock, &mut chip8);
+        }
+        assert_eq!(chip8.reg(1), 0x2A);
+        clock.advance(Duration::from_millis(50));
+        ticker.update(&clock, &mut chip8);
+        assert_eq!(chip8.delay_timer, 0x2A - 3);
+    }
+}
@@ -0,0 +1,187 @@
+use std::fmt;
+
+use crate::{ Chip8, Chip8Error, FlowControlOpcode, MetaOpcode };
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WatchTarget {
+    Reg(u8),
+    I,
+    DelayTimer,
+    SoundTimer,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum WatchCondition {
+    Equal(u16),
+    Above(u16),
+    Below(u16),
+}
+
+// Breaks once a register, I or a timer meets the condition.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Watch {
+    pub target: WatchTarget,
+    pub condition: WatchCondition,
+}
+
+impl Watch {
+    pub fn triggered(&self, chip8: &Chip8) -> bool {
+        let value = match self.target {
+            WatchTarget::Reg(x) => chip8.reg(x) as u16,
+            WatchTarget::I => chip8.reg_i,
+            WatchTarget::DelayTimer => chip8.delay_timer as u16,
+            WatchTarget::SoundTimer => chip8.sound_timer as u16,
+        };
+        return match self.condition {
+            WatchCondition::Equal(v) => value == v,
+            WatchCondition::Above(v) => value > v,
+            WatchCondition::Below(v) => value < v,
+        };
+    }
+}
+
+// Why continue_run came back.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StopReason {
+    Breakpoint(u16),
+    Watch(Watch),
+    // Reached a jump to itself, which would otherwise spin forever.
+    Halted(u16),
+    // Ran the whole budget without stopping for anything else.
+    CycleLimit(u64),
+}
+
+impl fmt::Display for StopReason {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        return match self {
+            StopReason::Breakpoint(addr) => write!(f, "Stopped at the breakpoint at {:03X}.", addr),
+            StopReason::Watch(watch) => write!(f, "Stopped on the watch on {:?} {:?}.", watch.target, watch.condition),
+            StopReason::Halted(addr) => write!(f, "Stopped at {:03X}, which jumps to itself.", addr),
+            StopReason::CycleLimit(cycles) => write!(f, "Stopped after {} instructions.", cycles),
+        };
+    }
+}
+
+#[derive(Default)]
+pub struct Debugger {
+    breakpoints: Vec<u16>,
+    watches: Vec<Watch>,
+}
+
+impl Debugger {
+    pub fn new() -> Debugger {
+        Debugger { breakpoints : Vec::new(), watches : Vec::new() }
+    }
+
+    pub fn add_watch(&mut self, watch: Watch) {
+        if !self.watches.contains(&watch) {
+            self.watches.push(watch);
+        }
+    }
+
+    pub fn remove_watch(&mut self, watch: Watch) -> bool {
+        let before = self.watches.len();
+        self.watches.retain(|&w| w != watch);
+        return self.watches.len() != before;
+    }
+
+    pub fn watches(&self) -> &[Watch] {
+        return &self.watches;
+    }
+
+    pub fn toggle_breakpoint(&mut self, addr: u16) -> bool {
+        if let Some(i) = self.breakpoints.iter().position(|&b| b == addr) {
+            self.breakpoints.remove(i);
+            return false;
+        }
+        self.breakpoints.push(addr);
+        return true;
+    }
+
+    pub fn breakpoints(&self) -> &[u16] {
+        return &self.breakpoints;
+    }
+
+    pub fn is_breakpoint(&self, addr: u16) -> bool {
+        return self.breakpoints.contains(&addr);
+    }
+
+    // Runs at most `max_cycles` instructions in place, so on an error
+    // the machine is left as the failing instruction found it. Always
+    // executes at least one instruction, so continuing from a breakpoint
+    // doesn't immediately stop on it again. Watches are checked after
+    // every instruction.
+    pub fn continue_run(&self, chip8: &mut Chip8, max_cycles: u64) -> Result<StopReason, Chip8Error> {
+        for _ in 0..max_cycles {
+            if let Ok(MetaOpcode::FlowControl(FlowControlOpcode::Jump { addr })) = chip8.peek_opcode() {
+                if addr == chip8.program_counter {
+                    return Ok(StopReason::Halted(addr));
+                }
+            }
+            chip8.step()?;
+            if self.is_breakpoint(chip8.program_counter) {
+                return Ok(StopReason::Breakpoint(chip8.program_counter));
+            }
+            if let Some(&watch) = self.watches.iter().find(|w| w.triggered(chip8)) {
+                return Ok(StopReason::Watch(watch));
+            }
+        }
+        return Ok(StopReason::CycleLimit(max_cycles));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Chip8Builder;
+
+    fn machine(rom: &[u8]) -> Chip8 {
+        let mut chip8 = Chip8Builder::new().seed(0).build();
+        chip8.load_rom_bytes(rom).unwrap();
+        return chip8;
+    }
+
+    #[test]
+    fn continue_stops_at_a_breakpoint() {
+        let mut chip8 = machine(&[0x60, 0x01, 0x61, 0x02, 0x62, 0x03, 0x12, 0x06]);
+        let mut debugger = Debugger::new();
+        debugger.toggle_breakpoint(0x204);
+        assert_eq!(debugger.continue_run(&mut chip8, 100).unwrap(), StopReason::Breakpoint(0x204));
+        assert_eq!(chip8.reg(1), 2);
+        assert_eq!(chip8.reg(2), 0);
+    }
+
+    #[test]
+    fn continue_stops_at_a_jump_to_itself() {
+        let mut chip8 = machine(&[0x60, 0x01, 0x12, 0x02]);
+        assert_eq!(Debugger::new().continue_run(&mut chip8, 100).unwrap(), StopReason::Halted(0x202));
+    }
+
+    #[test]
+    fn continue_gives_up_after_the_budget() {
+        // 1200 loops back to itself through 1202, so it never halts.
+        let mut chip8 = machine(&[0x12, 0x02, 0x12, 0x00]);
+        assert_eq!(Debugger::new().continue_run(&mut chip8, 10).unwrap(), StopReason::CycleLimit(10));
+        assert_eq!(chip8.report().cycles, 10);
+    }
+
+    #[test]
+    fn continue_keeps_progress_up_to_an_error() {
+        let mut chip8 = machine(&[0x60, 0x05, 0x00, 0xEE]);
+        assert!(matches!(Debugger::new().continue_run(&mut chip8, 100), Err(Chip8Error::StackUnderflow { pc : 0x202 })));
+        assert_eq!(chip8.reg(0), 5);
+    }
+
+    #[test]
+    fn continue_stops_when_a_watch_triggers() {
+        // Counts V5 up until it jumps back to 0x200 forever.
+        let mut chip8 = machine(&[0x75, 0x01, 0x12, 0x00]);
+        let mut debugger = Debugger::new();
+        let watch = Watch { target : WatchTarget::Reg(5), condition : WatchCondition::Equal(3) };
+        debugger.add_watch(watch);
+        assert_eq!(debugger.continue_run(&mut chip8, 100).unwrap(), StopReason::Watch(watch));
+        assert_eq!((chip8.reg(5), chip8.pc()), (3, 0x202));
+        assert!(debugger.remove_watch(watch));
+        assert!(debugger.watches().is_empty());
+    }
+}
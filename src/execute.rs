@@ -0,0 +1,402 @@
+use crate::{ Chip8, Chip8Error, DrawEvent, KeyEvent, KeyWait, KeyWaitAction, MemoryBounds, BIG_FONT_BASE, FONT_BASE, bcd, draw_sprite, read_memory, rng, scroll, sprite_shape, Warning };
+use crate::{ MetaOpcode, FlowControlOpcode, RegularOpcode };
+use MetaOpcode::*;
+use FlowControlOpcode::*;
+use RegularOpcode::*;
+
+impl Chip8 {
+    // Routes an opcode to its handler. Regular opcodes move on to the
+    // next instruction afterwards, flow control sets the PC itself.
+    //
+    // Handlers that set a flag read all their operands first and write
+    // VF last, so with VF as an operand or destination (8xF4, 8Fy4) the
+    // flag is what ends up in VF.
+    pub(crate) fn execute(&mut self, opcode: MetaOpcode) -> Result<(), Chip8Error> {
+        let extended = self.quirks.schip || self.quirks.xochip;
+        match opcode {
+            FlowControl(opcode) => match opcode {
+                Jump { addr } => self.jump(addr),
+                JumpPlusV0 { addr } => self.jump(addr + self.v[0] as u16),
+                Call { addr } => self.call(addr)?,
+                Return => self.ret()?,
+            },
+            Regular(opcode) => {
+                match opcode {
+                    // Native routines can't run here, but the report keeps their addresses.
+                    SysCall { .. } => (),
+                    ClearScreen => self.map_planes(|plane, _| vec![false; plane.len()]),
+                    ScrollDown { n } if extended => self.scroll_planes(0, n as isize),
+                    ScrollUp { n } if self.quirks.xochip => self.scroll_planes(0, -(n as isize)),
+                    ScrollRight if extended => self.scroll_planes(4, 0),
+                    ScrollLeft if extended => self.scroll_planes(-4, 0),
+                    LowRes if extended => self.set_hires(false),
+                    HighRes if extended => self.set_hires(true),
+                    SelectPlanes { planes } if self.quirks.xochip => self.selected_planes = planes & 3,
+                    LoadValToReg { x, value } => self.v[x as usize] = value,
+                    LoadRegToReg { x, y } => self.v[x as usize] = self.v[y as usize],
+                    LoadDelayTimerToReg { x } => self.v[x as usize] = self.delay_timer,
+                    LoadRegToDelayTimer { x } => self.delay_timer = self.v[x as usize],
+                    LoadRegToSoundTimer { x } => self.sound_timer = self.v[x as usize],
+                    OrRegReg { x, y } => self.v[x as usize] |= self.v[y as usize],
+                    AndRegReg { x, y } => self.v[x as usize] &= self.v[y as usize],
+                    XorRegReg { x, y } => self.v[x as usize] ^= self.v[y as usize],
+                    AddRegToReg { x, y } => self.add_reg_to_reg(x, y),
+                    SubRegFromReg { x, y } => self.sub(x, x, y),
+                    SubnRegFromReg { x, y } => self.sub(x, y, x),
+                    LoadValToI { value } => self.store_i(value),
+                    LoadSpriteLocationToI { x } => self.store_i((FONT_BASE + (self.v[x as usize] & 0xF) as usize * 5) as u16),
+                    LoadBigSpriteLocationToI { x } if extended =>
+                        self.store_i((BIG_FONT_BASE + (self.v[x as usize] & 0xF) as usize * 10) as u16),
+                    LoadRandomAndValToReg { x, value } => self.load_random(x, value),
+                    ShiftRightReg { x, y } => self.shift_right(x, y),
+                    ShiftLeftReg { x, y } => self.shift_left(x, y),
+                    LoadRegBcdToMem { x } => self.load_bcd(x)?,
+                    LoadRegsToMem { n } => self.load_regs_to_mem(n),
+                    LoadMemToRegs { n } => self.load_mem_to_regs(n),
+                    LoadPatternBuffer if self.quirks.xochip => self.load_pattern_buffer(),
+                    LoadRegToPitch { x } if self.quirks.xochip => self.pitch = self.v[x as usize],
+                    AddRegToI { x } => self.add_reg_to_i(x),
+                    SkipIfKeyPressed { x } => self.skip_if(self.keyboard[(self.v[x as usize] & 0xF) as usize]),
+                    SkipIfKeyNotPressed { x } => self.skip_if(!self.keyboard[(self.v[x as usize] & 0xF) as usize]),
+                    LoadKeyToReg { x } => self.load_key_to_reg(x)?,
+                    AddValToReg { x, value } => self.add_val_to_reg(x, value),
+                    SkipIfRegValEqual { x, value } => self.skip_if(self.v[x as usize] == value),
+                    SkipIfRegValNotEqual { x, value } => self.skip_if(self.v[x as usize] != value),
+                    SkipIfRegRegEqual { x, y } => self.skip_if(self.v[x as usize] == self.v[y as usize]),
+                    SkipIfRegRegNotEqual { x, y } => self.skip_if(self.v[x as usize] != self.v[y as usize]),
+                    DrawSprite { x, y, n } => self.draw(x, y, n)?,
+                    // Extensions that aren't enabled do nothing. Listing them
+                    // keeps the compiler checking that every opcode is handled.
+                    ScrollDown { .. } | ScrollUp { .. } | ScrollRight | ScrollLeft | LowRes | HighRes
+                    | LoadBigSpriteLocationToI { .. } | SelectPlanes { .. } | LoadPatternBuffer
+                    | LoadRegToPitch { .. } => (),
+                }
+                self.program_counter = self.program_counter.wrapping_add(2);
+            },
+            Unknown(_) => (),
+        }
+        return Ok(());
+    }
+
+    fn jump(&mut self, addr: u16) {
+        self.program_counter = addr;
+    }
+
+    fn call(&mut self, addr: u16) -> Result<(), Chip8Error> {
+        if self.sp as usize == self.stack.len() {
+            return Err(Chip8Error::StackOverflow { pc : self.program_counter });
+        }
+        self.stack[self.sp as usize] = self.program_counter;
+        self.sp += 1;
+        self.program_counter = addr;
+        return Ok(());
+    }
+
+    // The stack holds the address of the call itself.
+    fn ret(&mut self) -> Result<(), Chip8Error> {
+        if self.sp == 0 {
+            return Err(Chip8Error::StackUnderflow { pc : self.program_counter });
+        }
+        self.sp -= 1;
+        self.program_counter = self.stack[self.sp as usize].wrapping_add(2);
+        return Ok(());
+    }
+
+    // Skips over the next instruction; the router steps past this one.
+    fn skip_if(&mut self, condition: bool) {
+        if condition {
+            self.program_counter = self.program_counter.wrapping_add(2);
+        }
+    }
+
+    // Scrolling and clearing only touch the selected planes, so under
+    // XO-CHIP a plane that isn't selected keeps its pixels.
+    fn map_planes(&mut self, f: impl Fn(&[bool], usize) -> Vec<bool>) {
+        let planes = if self.quirks.xochip { self.selected_planes } else { 1 };
+        let (width, _) = self.resolution();
+        if planes & 1 != 0 {
+            self.display_memory = f(&self.display_memory, width);
+        }
+        if planes & 2 != 0 {
+            self.plane2 = f(&self.plane2, width);
+        }
+        self.mark_all_dirty();
+    }
+
+    fn scroll_planes(&mut self, dx: isize, dy: isize) {
+        let (dx, dy) = if self.quirks.half_lores_scroll && !self.hires { (dx / 2, dy / 2) } else { (dx, dy) };
+        self.map_planes(|plane, w| scroll(plane, w, dx, dy));
+    }
+
+    // Switching resolution clears the screen.
+    fn set_hires(&mut self, hires: bool) {
+        self.hires = hires;
+        let (width, height) = self.resolution();
+        self.display_memory = vec![false; width * height];
+        self.plane2 = vec![false; width * height];
+        // Anything dirtied before was in the other resolution.
+        self.dirty_rect = None;
+        self.mark_all_dirty();
+    }
+
+    fn load_random(&mut self, x: u8, value: u8) {
+        self.v[x as usize] = rng::random_byte(&mut self.rng) & value;
+    }
+
+    fn shift_right(&mut self, x: u8, y: u8) {
+        let source = self.v[if self.quirks.shift { x } else { y } as usize];
+        self.v[x as usize] = source >> 1;
+        self.v[0xF] = source & 1;
+    }
+
+    fn shift_left(&mut self, x: u8, y: u8) {
+        let source = self.v[if self.quirks.shift { x } else { y } as usize];
+        self.v[x as usize] = source << 1;
+        self.v[0xF] = source >> 7;
+    }
+
+    // Writes past the end of a resized `memory` are dropped.
+    fn write_memory(&mut self, addr: usize, value: u8) {
+        if let Some(byte) = self.memory.get_mut(addr & self.mem_mask as usize) {
+            *byte = value;
+        }
+    }
+
+    fn add_reg_to_reg(&mut self, x: u8, y: u8) {
+        let (sum, carry) = self.v[x as usize].overflowing_add(self.v[y as usize]);
+        self.v[x as usize] = sum;
+        self.v[0xF] = carry as u8;
+    }
+
+    // Vx = Va - Vb, with VF set when there was no borrow.
+    fn sub(&mut self, x: u8, a: u8, b: u8) {
+        let (difference, borrow) = self.v[a as usize].overflowing_sub(self.v[b as usize]);
+        self.v[x as usize] = difference;
+        self.v[0xF] = !borrow as u8;
+    }
+
+    fn load_bcd(&mut self, x: u8) -> Result<(), Chip8Error> {
+        let mut digits = bcd(self.v[x as usize]).to_vec();
+        let start = (self.reg_i & self.mem_mask) as usize;
+        let fit = self.memory.len().saturating_sub(start);
+        if digits.len() > fit {
+            match self.memory_bounds {
+                MemoryBounds::Wrap => (),
+                MemoryBounds::Clamp => {
+                    self.warnings.push(Warning::BcdClamped { addr : start, digits : fit });
+                    digits.truncate(fit);
+                },
+                // The first digit that doesn't fit, counted from I.
+                MemoryBounds::Error => return Err(Chip8Error::MemoryOutOfBounds { addr : start + fit }),
+            }
+        }
+        for (k, digit) in digits.iter().enumerate() {
+            self.write_memory(start + k, *digit);
+        }
+        return Ok(());
+    }
+
+    fn load_regs_to_mem(&mut self, n: u8) {
+        for k in 0..=(n as usize) {
+            self.write_memory(self.reg_i as usize + k, self.v[k]);
+        }
+        if !self.quirks.load_store {
+            self.store_i(self.reg_i.wrapping_add(n as u16 + 1));
+        }
+        if self.quirks.load_store_clears_vf {
+            self.v[0xF] = 0;
+        }
+    }
+
+    fn load_mem_to_regs(&mut self, n: u8) {
+        let values = read_memory(self, self.reg_i as usize, n as usize + 1);
+        self.v[..=(n as usize)].copy_from_slice(&values);
+        if !self.quirks.load_store {
+            self.store_i(self.reg_i.wrapping_add(n as u16 + 1));
+        }
+        if self.quirks.load_store_clears_vf {
+            self.v[0xF] = 0;
+        }
+    }
+
+    fn load_pattern_buffer(&mut self) {
+        let pattern = read_memory(self, self.reg_i as usize, 16);
+        self.pattern_buffer.copy_from_slice(&pattern);
+    }
+
+    // XO-CHIP addresses 64K, so only the standard modes can
+    // overflow past the end of memory. The flag sees the sum before
+    // it's masked back into memory.
+    fn add_reg_to_i(&mut self, x: u8) {
+        let sum = self.reg_i.wrapping_add(self.v[x as usize] as u16);
+        if self.quirks.add_i {
+            self.v[0xF] = (sum > self.mem_mask) as u8;
+        }
+        self.store_i(sum);
+    }
+
+    // Fx0A only takes fresh presses: it reads the key events queued
+    // since the wait began, so a key already down then never counts
+    // and a held key can't satisfy several waits in a row. Like the
+    // VIP, the wait ends when the pressed key is released unless
+    // `fx0a_on_press` is set.
+    fn load_key_to_reg(&mut self, x: u8) -> Result<(), Chip8Error> {
+        let mut wait = self.key_wait.unwrap_or(KeyWait { pressed : None, cycles : 0, seen : self.key_events.len() });
+        let mut done = None;
+        for &event in self.key_events.iter().skip(wait.seen) {
+            match event {
+                KeyEvent::Down(key) if wait.pressed.is_none() => {
+                    wait.pressed = Some(key);
+                    if self.quirks.fx0a_on_press {
+                        done = Some(key);
+                        break;
+                    }
+                },
+                KeyEvent::Up(key) if wait.pressed == Some(key) => {
+                    done = Some(key);
+                    break;
+                },
+                _ => (),
+            }
+        }
+        wait.seen = self.key_events.len();
+        match done {
+            Some(key) => {
+                self.v[x as usize] = key;
+                self.key_wait = None;
+            },
+            // Stay on this instruction until a key is pressed, or until
+            // the timeout gives up on one.
+            _ => {
+                wait.cycles += 1;
+                match self.key_wait_timeout {
+                    Some(timeout) if wait.cycles > timeout.cycles => match timeout.action {
+                        // Left on the Fx0A, which fails again if stepped.
+                        KeyWaitAction::Abort => {
+                            self.key_wait = Some(wait);
+                            return Err(Chip8Error::WaitTimedOut { pc : self.program_counter });
+                        },
+                        KeyWaitAction::PressKey(key) => {
+                            self.v[x as usize] = key & 0xF;
+                            self.key_wait = None;
+                        },
+                    },
+                    _ => {
+                        self.program_counter = self.program_counter.wrapping_sub(2);
+                        self.key_wait = Some(wait);
+                    },
+                }
+            },
+        }
+        return Ok(());
+    }
+
+    fn add_val_to_reg(&mut self, x: u8, value: u8) {
+        let (sum, carry) = self.v[x as usize].overflowing_add(value);
+        self.v[x as usize] = sum;
+        if self.quirks.add_val_sets_vf {
+            self.v[0xF] = carry as u8;
+        }
+    }
+
+    // With both XO-CHIP planes selected the sprite data for
+    // the second plane directly follows the first. SCHIP hires sets VF
+    // to the number of rows that collided or were clipped, otherwise
+    // VF only tells whether anything collided.
+    fn draw(&mut self, x: u8, y: u8, n: u8) -> Result<(), Chip8Error> {
+        let (row_bytes, height) = match sprite_shape(n, &self.quirks) {
+            Some(shape) => shape,
+            None => return Ok(()),
+        };
+        let planes = if self.quirks.xochip { self.selected_planes } else { 1 };
+        // I is masked like any other address, so the end of memory is the
+        // end of the address space.
+        let start = (self.reg_i & self.mem_mask) as usize;
+        let len = row_bytes * height * planes.count_ones() as usize;
+        let fit = self.memory.len().saturating_sub(start);
+        if self.memory_bounds == MemoryBounds::Error && len > fit {
+            // The first sprite byte that isn't in memory, counted from I.
+            return Err(Chip8Error::MemoryOutOfBounds { addr : start + fit });
+        }
+        let (width, _) = self.resolution();
+        let start_x = self.v[x as usize] as usize;
+        let start_y = self.v[y as usize] as usize;
+        let mut addr = start;
+        let (mut rows, mut collisions) = (0, 0);
+        for bit in [1, 2] {
+            if planes & bit == 0 {
+                continue;
+            }
+            let rows_in_memory = self.memory.len().saturating_sub(addr) / row_bytes;
+            let sprite_rows = match self.memory_bounds {
+                MemoryBounds::Clamp if rows_in_memory < height => {
+                    self.warnings.push(Warning::SpriteClamped { addr, rows : rows_in_memory, height });
+                    rows_in_memory
+                },
+                _ => height,
+            };
+            let sprite = read_memory(self, addr, row_bytes * sprite_rows);
+            let plane = if bit == 1 { &mut self.display_memory } else { &mut self.plane2 };
+            let (plane_rows, plane_clipped, plane_pixels, plane_dirty) =
+                draw_sprite(plane, width, &sprite, row_bytes, start_x, start_y, &self.quirks);
+            rows = rows.max(plane_rows + plane_clipped);
+            if let Some(rect) = plane_dirty {
+                self.mark_dirty(rect);
+            }
+            collisions += plane_pixels;
+            addr += row_bytes * height;
+        }
+        self.v[0xF] = if self.quirks.schip && self.hires { rows } else { (collisions > 0) as u8 };
+        if self.log_draws {
+            self.draw_log.push(DrawEvent {
+                pc : self.program_counter,
+                x : start_x as u8,
+                y : start_y as u8,
+                height : height as u8,
+                collisions,
+                vf : self.v[0xF],
+            });
+        }
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{ Chip8, Chip8Builder };
+
+    fn machine() -> Chip8 {
+        return Chip8Builder::new().seed(0).build();
+    }
+
+    #[test]
+    fn add_reg_to_reg_sets_the_carry() {
+        let mut chip8 = machine();
+        chip8.v[1] = 0xF0;
+        chip8.v[2] = 0x20;
+        chip8.add_reg_to_reg(1, 2);
+        assert_eq!((chip8.v[1], chip8.v[0xF]), (0x10, 1));
+        chip8.add_reg_to_reg(1, 2);
+        assert_eq!((chip8.v[1], chip8.v[0xF]), (0x30, 0));
+    }
+
+    #[test]
+    fn sub_sets_vf_without_a_borrow() {
+        let mut chip8 = machine();
+        chip8.v[1] = 5;
+        chip8.v[2] = 3;
+        chip8.sub(3, 1, 2);
+        assert_eq!((chip8.v[3], chip8.v[0xF]), (2, 1));
+        chip8.sub(3, 2, 1);
+        assert_eq!((chip8.v[3], chip8.v[0xF]), (0xFE, 0));
+    }
+
+    #[test]
+    fn skip_if_only_skips_on_true() {
+        let mut chip8 = machine();
+        chip8.skip_if(false);
+        assert_eq!(chip8.program_counter, 0x200);
+        chip8.skip_if(true);
+        assert_eq!(chip8.program_counter, 0x202);
+    }
+}
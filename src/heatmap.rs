@@ -0,0 +1,105 @@
+use crate::{ Chip8, MetaOpcode, RegularOpcode, sprite_shape };
+
+// How much of a count is left after each timer tick, so the map shows
+// what the rom is doing now rather than everything it ever did.
+const DECAY: f32 = 0.95;
+
+// Data reads and writes per address, collected when `track_memory` is on.
+// Instruction fetches aren't counted, they'd drown out everything else.
+#[derive(Clone, Debug)]
+pub struct MemoryHeat {
+    reads: Vec<f32>,
+    writes: Vec<f32>,
+}
+
+// The (reads, writes) an instruction is about to make, worked out from
+// the opcode and the machine before it runs.
+pub fn accesses(chip8: &Chip8, opcode: &MetaOpcode) -> (Vec<usize>, Vec<usize>) {
+    let from_i = |len: usize| (0..len)
+        .map(|k| (chip8.reg_i as usize + k) & chip8.mem_mask as usize)
+        .collect::<Vec<usize>>();
+    let planes = if chip8.quirks.xochip { chip8.selected_planes.count_ones() as usize } else { 1 };
+    return match opcode {
+        MetaOpcode::Regular(RegularOpcode::LoadRegsToMem { n }) => (Vec::new(), from_i(*n as usize + 1)),
+        MetaOpcode::Regular(RegularOpcode::LoadRegBcdToMem { .. }) => (Vec::new(), from_i(3)),
+        MetaOpcode::Regular(RegularOpcode::LoadMemToRegs { n }) => (from_i(*n as usize + 1), Vec::new()),
+        MetaOpcode::Regular(RegularOpcode::LoadPatternBuffer) if chip8.quirks.xochip => (from_i(16), Vec::new()),
+        MetaOpcode::Regular(RegularOpcode::DrawSprite { n, .. }) => match sprite_shape(*n, &chip8.quirks) {
+            Some((row_bytes, rows)) => (from_i(row_bytes * rows * planes), Vec::new()),
+            None => (Vec::new(), Vec::new()),
+        },
+        _ => (Vec::new(), Vec::new()),
+    };
+}
+
+impl MemoryHeat {
+    // Sized for XO-CHIP, so a rom switching modes doesn't need a new map.
+    pub fn new() -> MemoryHeat {
+        return MemoryHeat { reads : vec![0.0; 0x10000], writes : vec![0.0; 0x10000] };
+    }
+
+    pub fn record(&mut self, chip8: &Chip8, opcode: &MetaOpcode) {
+        let (reads, writes) = accesses(chip8, opcode);
+        for addr in reads {
+            self.reads[addr] += 1.0;
+        }
+        for addr in writes {
+            self.writes[addr] += 1.0;
+        }
+    }
+
+    pub fn reads(&self) -> &[f32] {
+        return &self.reads;
+    }
+
+    pub fn writes(&self) -> &[f32] {
+        return &self.writes;
+    }
+
+    pub(crate) fn decay(&mut self) {
+        self.reads.iter_mut().chain(self.writes.iter_mut()).for_each(|count| *count *= DECAY);
+    }
+
+    // One character per `block` bytes of the first `size`, 64 to a line:
+    // blank when untouched, r or w for whichever happened more, in
+    // capitals once a block has seen ten or more recent accesses.
+    pub fn render(&self, size: usize, block: usize) -> Vec<String> {
+        let size = size.min(self.reads.len());
+        let cells = (0..size).step_by(block)
+            .map(|start| {
+                let end = (start + block).min(size);
+                let reads = self.reads[start..end].iter().sum::<f32>();
+                let writes = self.writes[start..end].iter().sum::<f32>();
+                let (c, count) = if writes >= reads { ('w', writes) } else { ('r', reads) };
+                match count {
+                    count if count >= 10.0 => c.to_ascii_uppercase(),
+                    count if count >= 0.5 => c,
+                    _ => ' ',
+                }
+            })
+            .collect::<Vec<char>>();
+        return cells.chunks(64).map(|line| line.iter().collect()).collect();
+    }
+}
+
+impl Default for MemoryHeat {
+    fn default() -> MemoryHeat {
+        return MemoryHeat::new();
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Chip8Builder;
+
+    #[test]
+    fn fx55_counts_writes_to_the_stored_addresses() {
+        let mut chip8 = Chip8Builder::new().track_memory(true).build();
+        chip8.load_rom_bytes(&[0xA3, 0x00, 0xF2, 0x55]).unwrap();
+        chip8.step().unwrap();
+        chip8.step().unwrap();
+        let heat = chip8.memory_heat().unwrap();
+        assert_eq!(&heat.writes()[0x2FF..0x304], &[0.0, 1.0, 1.0, 1.0, 0.0]);
+        assert!(heat.reads().iter().all(|&count| count == 0.0));
+    }
+}
@@ -0,0 +1,89 @@
+// Sticky keys, for players who can't hold several keys at once: a tap
+// latches a key down until the next tap of it releases it. This sits
+// in front of `queue_key` or `set_key` and never touches the machine,
+// it only decides what state to hand it. A latched key is pressed as
+// far as the program can tell, so Ex9E skips on it and Fx0A takes it;
+// with the VIP's release-ends-the-wait behavior that happens on the
+// tap that unlatches it, with `fx0a_on_press` on the one latching it.
+#[derive(Clone, Debug, Default)]
+pub struct StickyKeys {
+    enabled: bool,
+    // What the player's fingers are doing.
+    held: [bool; 16],
+    // What the machine is told.
+    latched: [bool; 16],
+}
+
+impl StickyKeys {
+    pub fn new(enabled: bool) -> StickyKeys {
+        return StickyKeys { enabled, .. StickyKeys::default() };
+    }
+
+    pub fn enabled(&self) -> bool {
+        return self.enabled;
+    }
+
+    // Turning it off drops every latch, leaving only held keys down.
+    pub fn set_enabled(&mut self, enabled: bool) {
+        self.enabled = enabled;
+        if !enabled {
+            self.latched = self.held;
+        }
+    }
+
+    // Takes a physical press or release and gives back whether the key
+    // should be down for the machine. When disabled keys pass through.
+    pub fn key(&mut self, key: u8, pressed: bool) -> bool {
+        let key = (key & 0xF) as usize;
+        let tapped = pressed && !self.held[key];
+        self.held[key] = pressed;
+        if !self.enabled {
+            self.latched[key] = pressed;
+        } else if tapped {
+            self.latched[key] = !self.latched[key];
+        }
+        return self.latched[key];
+    }
+
+    pub fn latched(&self) -> &[bool; 16] {
+        return &self.latched;
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tapping_latches_and_tapping_again_releases() {
+        let mut sticky = StickyKeys::new(true);
+        assert!(sticky.key(3, true));
+        assert!(sticky.key(3, false));
+        assert!(sticky.latched()[3]);
+        assert!(!sticky.key(3, true));
+        assert!(!sticky.key(3, false));
+        assert!(!sticky.latched()[3]);
+    }
+
+    #[test]
+    fn disabled_keys_pass_through() {
+        let mut sticky = StickyKeys::new(false);
+        assert!(sticky.key(3, true));
+        assert!(!sticky.key(3, false));
+    }
+
+    #[test]
+    fn latched_key_satisfies_ex9e() {
+        let mut chip8 = crate::Chip8Builder::new().build();
+        chip8.load_rom_bytes(&[0x60, 0x03, 0xE0, 0x9E, 0x12, 0x04, 0x12, 0x06]).unwrap();
+        let mut sticky = StickyKeys::new(true);
+        for (key, pressed) in [(3, true), (3, false)] {
+            let down = sticky.key(key, pressed);
+            chip8.set_key(key, down);
+        }
+        for _ in 0..2 {
+            chip8.step().unwrap();
+        }
+        assert_eq!(chip8.pc(), 0x206);
+    }
+}
@@ -0,0 +1,2940 @@
+#![allow(clippy::needless_return, clippy::identity_op)]
+
+use std::fs;
+use std::collections::VecDeque;
+use std::fmt;
+use std::io::{ self, Read };
+use std::time::{ Duration, Instant };
+
+pub mod audio;
+pub mod cache;
+pub mod clipboard;
+pub mod clock;
+pub mod debugger;
+mod execute;
+pub mod heatmap;
+pub mod input;
+pub mod monitor;
+pub mod panel;
+pub mod profiles;
+pub mod render;
+pub mod shutdown;
+pub mod report;
+pub mod rng;
+pub mod state;
+pub mod trace;
+pub mod validate;
+pub mod watch;
+
+#[derive(Clone)]
+pub struct Chip8 {
+    pub memory: Vec<u8>,
+    // Addresses formed from I wrap at the end of memory.
+    mem_mask: u16,
+    pub program_counter: u16,
+
+    pub v: Vec<u8>,
+    pub reg_i: u16,
+
+    stack: [u16; 16],
+    sp: u8,
+
+    pub delay_timer: u8,
+    pub sound_timer: u8,
+
+    keyboard: [bool; 16],
+    // Input queued since the last frame, latched into `keyboard` once
+    // per frame like the VIP's keypad scan.
+    key_input: [bool; 16],
+    // Edges seen by `keyboard`, oldest first, until drained.
+    key_events: Vec<KeyEvent>,
+    key_wait: Option<KeyWait>,
+    pub display_memory: Vec<bool>,
+    // XO-CHIP's second bit plane; `display_memory` is the first.
+    pub plane2: Vec<bool>,
+    // Bitmask of the planes drawn to, cleared and scrolled (XO-CHIP Fn01).
+    selected_planes: u8,
+    // SCHIP 128x64 mode, switched with 00FF and back with 00FE.
+    hires: bool,
+    // Whether the display changed since on_draw, and since step_frame
+    // last reported it; each consumer clears only its own.
+    display_dirty: bool,
+    frame_drew: bool,
+    // The part of the display changed since it was last presented.
+    dirty_rect: Option<DirtyRect>,
+
+    rng: rng::Chip8Rng,
+    seed: Option<u64>,
+
+    load_address: u16,
+
+    quirks: Quirks,
+    auto_quirks: bool,
+    strict_quirks: bool,
+    enforce_alignment: bool,
+    memory_bounds: MemoryBounds,
+    key_wait_timeout: Option<KeyWaitTimeout>,
+    font: Font,
+    log_draws: bool,
+    instructions_per_frame: u32,
+    quirk_log: Vec<QuirkEvent>,
+    draw_log: Vec<DrawEvent>,
+    warnings: Vec<Warning>,
+    // PC of the last Fx55/Fx65 whose effect on I hasn't been observed yet.
+    pending_load_store: Option<u16>,
+    report: report::Report,
+    heat: Option<heatmap::MemoryHeat>,
+    // The trace_line of the last `crash_trace` instructions, oldest
+    // first, for post_mortem.
+    crash_trace: usize,
+    recent_trace: VecDeque<String>,
+    // A debugging aid: the CPU keeps running but the 60Hz timers hold
+    // their values, so they don't race ahead between manual steps.
+    freeze_timers: bool,
+    // Set while the window is out of focus, to the freeze_timers
+    // setting to go back to when it's focused again.
+    unfocused: Option<bool>,
+
+    pub pattern_buffer: [u8; 16],
+    pub pitch: u8,
+}
+
+impl Chip8 {
+    pub fn report(&self) -> &report::Report {
+        return &self.report;
+    }
+
+    pub fn executed_opcodes(&self) -> Vec<&'static str> {
+        return self.report.executed_opcodes();
+    }
+
+    pub fn quirk_log(&self) -> &[QuirkEvent] {
+        return &self.quirk_log;
+    }
+
+    pub fn drain_draw_log(&mut self) -> Vec<DrawEvent> {
+        return std::mem::take(&mut self.draw_log);
+    }
+
+    // Problems that didn't stop the program, oldest first, for the
+    // frontend to show.
+    pub fn drain_warnings(&mut self) -> Vec<Warning> {
+        return std::mem::take(&mut self.warnings);
+    }
+
+    // Takes effect immediately; frontends should use `queue_key`.
+    pub fn set_key(&mut self, key: u8, pressed: bool) {
+        let key = key & 0xF;
+        if self.keyboard[key as usize] != pressed {
+            self.key_events.push(if pressed { KeyEvent::Down(key) } else { KeyEvent::Up(key) });
+        }
+        self.keyboard[key as usize] = pressed;
+    }
+
+    pub fn queue_key(&mut self, key: u8, pressed: bool) {
+        self.key_input[(key & 0xF) as usize] = pressed;
+    }
+
+    // Keys that changed since the last latch are applied releases first,
+    // then presses, each in ascending key order, so a replay of the same
+    // input always produces the same events.
+    pub fn latch_keys(&mut self) {
+        for pressed in [false, true] {
+            for key in 0..16 {
+                if self.key_input[key as usize] == pressed {
+                    self.set_key(key, pressed);
+                }
+            }
+        }
+    }
+
+    // For when the frontend stops seeing key releases, e.g. on losing
+    // focus, so no key stays stuck down.
+    pub fn release_keys(&mut self) {
+        self.key_input = [false; 16];
+        self.latch_keys();
+    }
+
+    // Pauses step_frame with every key released and the timers frozen,
+    // so the game doesn't carry on in the background or come back with
+    // a key stuck down.
+    pub fn focus_lost(&mut self) {
+        if self.unfocused.is_none() {
+            self.unfocused = Some(self.freeze_timers);
+        }
+        self.release_keys();
+        self.set_freeze_timers(true);
+    }
+
+    pub fn focus_gained(&mut self) {
+        if let Some(freeze) = self.unfocused.take() {
+            self.set_freeze_timers(freeze);
+        }
+    }
+
+    pub fn paused(&self) -> bool {
+        return self.unfocused.is_some();
+    }
+
+    // A waiting Fx0A has read everything up to now, so it carries on
+    // from the start of the emptied queue.
+    pub fn drain_key_events(&mut self) -> Vec<KeyEvent> {
+        if let Some(wait) = &mut self.key_wait {
+            wait.seen = 0;
+        }
+        return std::mem::take(&mut self.key_events);
+    }
+
+    // VF doubles as the flag register, so instructions that set a flag
+    // overwrite whatever was written here.
+    pub fn reg(&self, x: u8) -> u8 {
+        return self.v[(x & 0xF) as usize];
+    }
+
+    // Also the way to force VF: flag-setting instructions write it last
+    // and skips read it like any register, so a 3Fkk right after an 8xy4
+    // sees the flag that was just written, forced or not.
+    pub fn set_reg(&mut self, x: u8, val: u8) {
+        self.v[(x & 0xF) as usize] = val;
+    }
+
+    pub fn i(&self) -> u16 {
+        return self.reg_i;
+    }
+
+    pub fn set_i(&mut self, val: u16) {
+        self.store_i(val);
+    }
+
+    // Every write to I goes through here and is masked to the address
+    // space, 12 bits or XO-CHIP's 16, so I always points into memory
+    // and nothing reading through it can run out of bounds.
+    fn store_i(&mut self, val: u16) {
+        self.reg_i = val & self.mem_mask;
+    }
+
+    pub fn pc(&self) -> u16 {
+        return self.program_counter;
+    }
+
+    // One 60Hz tick of the delay and sound timers. Nothing else moves
+    // them, so between ticks Fx07 reads back exactly what Fx15 set.
+    pub fn tick_timers(&mut self) {
+        if self.freeze_timers {
+            return;
+        }
+        self.delay_timer = self.delay_timer.saturating_sub(1);
+        self.sound_timer = self.sound_timer.saturating_sub(1);
+        if let Some(heat) = &mut self.heat {
+            heat.decay();
+        }
+    }
+
+    pub fn timers_frozen(&self) -> bool {
+        return self.freeze_timers;
+    }
+
+    pub fn set_freeze_timers(&mut self, freeze: bool) {
+        self.freeze_timers = freeze;
+    }
+
+    pub fn memory_heat(&self) -> Option<&heatmap::MemoryHeat> {
+        return self.heat.as_ref();
+    }
+
+    // The active part of the stack, outermost call first. Entries are
+    // the addresses of the calls; returns resume two bytes after them.
+    pub fn stack(&self) -> &[u16] {
+        return &self.stack[..self.sp as usize];
+    }
+
+    pub fn stack_depth(&self) -> usize {
+        return self.sp as usize;
+    }
+
+    pub fn set_stack_entry(&mut self, depth: usize, addr: u16) -> Result<(), Chip8Error> {
+        if depth >= self.sp as usize {
+            return Err(Chip8Error::NoStackEntry { depth });
+        }
+        self.stack[depth] = addr;
+        return Ok(());
+    }
+
+    pub fn peek(&self, addr: u16) -> Result<u8, Chip8Error> {
+        return self.memory.get(addr as usize).cloned()
+            .ok_or(Chip8Error::MemoryOutOfBounds { addr : addr as usize });
+    }
+
+    // `len` bytes from I on, wrapping at the end of memory the way
+    // Fx33, Fx55 and Fx65 address them, e.g. the digits Fx33 just
+    // stored are peek_at_i(3).
+    pub fn peek_at_i(&self, len: usize) -> Vec<u8> {
+        return read_memory(self, self.reg_i as usize, len);
+    }
+
+    pub fn poke(&mut self, addr: u16, value: u8) -> Result<(), Chip8Error> {
+        let byte = self.memory.get_mut(addr as usize)
+            .ok_or(Chip8Error::MemoryOutOfBounds { addr : addr as usize })?;
+        *byte = value;
+        return Ok(());
+    }
+
+    pub fn keys(&self) -> &[bool; 16] {
+        return &self.keyboard;
+    }
+
+    pub fn mem_mask(&self) -> u16 {
+        return self.mem_mask;
+    }
+
+    // Zeroed memory runs as 0000 no-ops forever, so memory from the load
+    // address on being all zero means there's no program to run.
+    pub fn program_loaded(&self) -> bool {
+        return self.memory.iter().skip(self.load_address as usize).any(|&b| b != 0);
+    }
+
+    // Writes memory from the load address to the last non-zero byte, so
+    // a patched program can be saved as a rom again.
+    pub fn export_rom(&self, path: &str) -> Result<(), Chip8Error> {
+        let program = self.memory.get(self.load_address as usize..).unwrap_or_default();
+        let end = program.iter().rposition(|&b| b != 0).map_or(0, |last| last + 1);
+        fs::write(path, &program[..end])?;
+        return Ok(());
+    }
+
+    // True while an Fx0A is blocking on a key press.
+    pub fn waiting_for_key(&self) -> bool {
+        return self.key_wait.is_some();
+    }
+
+    // Decodes the instruction at PC without executing it.
+    pub fn peek_opcode(&self) -> Result<MetaOpcode, Chip8Error> {
+        return fetch_checked(self).map(parse_opcode);
+    }
+
+    pub fn quirks(&self) -> Quirks {
+        return self.quirks;
+    }
+
+    // Takes effect from the next instruction.
+    pub fn set_quirk(&mut self, quirk: QuirkKind, on: bool) {
+        let field = match quirk {
+            QuirkKind::Shift => &mut self.quirks.shift,
+            QuirkKind::LoadStore => &mut self.quirks.load_store,
+            QuirkKind::LoadStoreClearsVf => &mut self.quirks.load_store_clears_vf,
+            QuirkKind::AddI => &mut self.quirks.add_i,
+            QuirkKind::AddValSetsVf => &mut self.quirks.add_val_sets_vf,
+            QuirkKind::Fx0aOnPress => &mut self.quirks.fx0a_on_press,
+            QuirkKind::ClampOrigin => &mut self.quirks.clamp_origin,
+            QuirkKind::WrapSprites => &mut self.quirks.wrap_sprites,
+            QuirkKind::HalfLoresScroll => &mut self.quirks.half_lores_scroll,
+        };
+        *field = on;
+    }
+
+    pub fn load_rom_bytes(&mut self, rom: &[u8]) -> Result<(), Chip8Error> {
+        // The profile can switch modes, and with them the memory size.
+        let quirks = match profiles::auto_quirks(rom) {
+            Some(quirks) if self.auto_quirks => quirks,
+            _ => self.quirks,
+        };
+        let mut memory = self.memory.to_vec();
+        memory.resize(memory_size(&quirks), 0);
+        let base = self.load_address as usize;
+        // Even an empty rom needs somewhere to start running from.
+        if base >= memory.len() {
+            return Err(Chip8Error::MemoryOutOfBounds { addr : base });
+        }
+        let max = memory.len() - base;
+        if rom.len() > max {
+            return Err(Chip8Error::RomTooLarge { size : rom.len(), max });
+        }
+        // Memory past the rom is zeroed, so a trailing odd byte
+        // fetches as the high half of an opcode ending in 00.
+        if rom.len() & 1 == 1 {
+            self.warnings.push(Warning::OddRom { len : rom.len() });
+        }
+        // FIXME: Still unsure if this is the correct
+        // way to replace a part of vec.
+        memory.splice(base..(base + rom.len()), rom.iter().cloned());
+        self.mem_mask = (memory.len() - 1) as u16;
+        self.memory = memory;
+        self.quirks = quirks;
+        return Ok(());
+    }
+
+    pub fn load_rom(&mut self, filename: &str) -> Result<(), Chip8Error> {
+        let rom = fs::read(filename)?;
+        return self.load_rom_bytes(&rom);
+    }
+
+    pub fn step(&mut self) -> Result<(), Chip8Error> {
+        if self.crash_trace > 0 {
+            if self.recent_trace.len() == self.crash_trace {
+                self.recent_trace.pop_front();
+            }
+            self.recent_trace.push_back(self.trace_line());
+        }
+        if self.enforce_alignment && self.program_counter & 1 == 1 {
+            return Err(Chip8Error::MisalignedPc { pc : self.program_counter });
+        }
+        let raw_opcode = fetch_checked(self)?;
+        let meta_opcode = parse_opcode(raw_opcode);
+        check_quirks(self, &meta_opcode);
+        self.report.record(raw_opcode, &meta_opcode);
+        if let Some(mut heat) = self.heat.take() {
+            heat.record(self, &meta_opcode);
+            self.heat = Some(heat);
+        }
+        self.execute(meta_opcode)?;
+        self.report.observe(self.sp, self.sound_timer);
+        return Ok(());
+    }
+
+    // What led up to a failed step: the kept trace lines, the failing
+    // instruction last, then the registers and stack as it left them.
+    // Empty unless the machine was built with `crash_trace`.
+    pub fn post_mortem(&self) -> String {
+        if self.crash_trace == 0 {
+            return String::new();
+        }
+        let mut lines = vec![format!("The last {} instructions:", self.recent_trace.len())];
+        lines.extend(self.recent_trace.iter().cloned());
+        lines.push(registers_text(self));
+        return lines.join("\n");
+    }
+
+    // Runs `instructions_per_frame` instructions and one timer tick.
+    pub fn step_frame(&mut self) -> Result<FrameResult, Chip8Error> {
+        if self.paused() {
+            return Ok(FrameResult { drew : false, sound : false });
+        }
+        for _ in 0..self.instructions_per_frame {
+            self.step()?;
+        }
+        self.tick_timers();
+        let drew = self.frame_drew;
+        self.frame_drew = false;
+        return Ok(FrameResult { drew, sound : self.sound_timer > 0 });
+    }
+
+    // A frame of emulation followed by presenting the latest display,
+    // which happens whether or not the CPU drew during the frame.
+    pub fn step_frame_presenting(&mut self, presenter: &mut impl render::Presenter) -> Result<FrameResult, Chip8Error> {
+        let result = self.step_frame()?;
+        let dirty = self.take_dirty_rect();
+        presenter.present(&self.display_memory, dirty);
+        return Ok(result);
+    }
+
+    pub fn dirty_rect(&self) -> Option<DirtyRect> {
+        return self.dirty_rect;
+    }
+
+    // The region changed since the last call, for renderers presenting
+    // by themselves rather than through step_frame_presenting.
+    pub fn take_dirty_rect(&mut self) -> Option<DirtyRect> {
+        return self.dirty_rect.take();
+    }
+
+    fn mark_dirty(&mut self, rect: DirtyRect) {
+        self.display_dirty = true;
+        self.frame_drew = true;
+        self.dirty_rect = Some(self.dirty_rect.map_or(rect, |dirty| dirty.union(rect)));
+    }
+
+    fn mark_all_dirty(&mut self) {
+        let (width, height) = self.resolution();
+        self.mark_dirty(DirtyRect { x : 0, y : 0, width, height });
+    }
+
+    // Returns whether PC reached `target` within `max_cycles` instructions.
+    pub fn run_until_pc(&mut self, target: u16, max_cycles: usize) -> Result<bool, Chip8Error> {
+        for _ in 0..max_cycles {
+            if self.program_counter == target {
+                return Ok(true);
+            }
+            self.step()?;
+        }
+        return Ok(self.program_counter == target);
+    }
+
+    // One line per instruction, describing the state before it executes:
+    // PC:0x0200 OP:A22A I:0x022A V0..VF:[00,01,...,0F] DT:0 ST:0
+    // Addresses are four hex digits, registers two, timers decimal.
+    pub fn trace_line(&self) -> String {
+        let v = self.v.iter().map(|r| format!("{:02X}", r)).collect::<Vec<String>>();
+        return format!("PC:0x{:04X} OP:{:04X} I:0x{:04X} V0..VF:[{}] DT:{} ST:{}",
+            self.program_counter, fetch_opcode(self), self.reg_i, v.join(","),
+            self.delay_timer, self.sound_timer);
+    }
+
+    // (x, y) of every pixel that differs from `expected`.
+    pub fn display_diff(&self, expected: &[bool]) -> Vec<(usize, usize)> {
+        let len = self.display_memory.len().max(expected.len());
+        return (0..len)
+            .filter(|&i| self.display_memory.get(i) != expected.get(i))
+            .map(|i| (i % self.resolution().0, i / self.resolution().0))
+            .collect();
+    }
+
+    pub fn hires(&self) -> bool {
+        return self.hires;
+    }
+
+    // (width, height) of the display.
+    pub fn resolution(&self) -> (usize, usize) {
+        return if self.hires { (128, 64) } else { (64, 32) };
+    }
+
+    // Checks the invariants a freshly loaded machine should hold.
+    pub fn self_test(&self) -> Result<(), Chip8Error> {
+        if self.memory.get(FONT_BASE..(FONT_BASE + 80)) != Some(&self.font.small[..])
+            || self.memory.get(BIG_FONT_BASE..(BIG_FONT_BASE + 160)) != Some(&self.font.big[..]) {
+            return Err(Chip8Error::SelfTestFailed("The font in memory is corrupted."));
+        }
+        if self.program_counter as usize + 1 >= self.memory.len() {
+            return Err(Chip8Error::SelfTestFailed("The program counter is outside of memory."));
+        }
+        if self.sp as usize > self.stack.len() {
+            return Err(Chip8Error::SelfTestFailed("The stack pointer is out of range."));
+        }
+        return Ok(());
+    }
+
+    // Sprite data in memory as rows of `#` and spaces, shaped the way
+    // Dxyn with this height would draw it, so empty for a height of 0
+    // outside SCHIP and XO-CHIP.
+    pub fn render_sprite(&self, addr: u16, height: u8) -> String {
+        let (row_bytes, rows) = match sprite_shape(height, &self.quirks) {
+            Some(shape) => shape,
+            None => return String::new(),
+        };
+        return read_memory(self, addr as usize, row_bytes * rows)
+            .chunks(row_bytes)
+            .map(|row| row.iter().flat_map(byte_to_bits).map(|p| if p {'#'} else {' '}).collect::<String>() + "\n")
+            .collect();
+    }
+
+    // Whether drawing the 8-pixel-wide `sprite` at (x, y) would turn a
+    // pixel off, found by drawing it on a copy of the display.
+    pub fn would_collide(&self, x: u8, y: u8, sprite: &[u8]) -> bool {
+        let (width, _) = self.resolution();
+        let mut plane = self.display_memory.clone();
+        let (_, _, pixels, _) = draw_sprite(&mut plane, width, sprite, 1, x as usize, y as usize, &self.quirks);
+        return pixels > 0;
+    }
+
+    pub fn frame_buffer(&self) -> &[bool] {
+        return &self.display_memory;
+    }
+
+    pub fn display_snapshot(&self) -> Vec<bool> {
+        return self.display_memory.clone();
+    }
+
+    // FNV-1a over the pixels. Unlike std's hasher it won't change between
+    // Rust versions, so tests can keep hashes of expected screens.
+    pub fn display_hash(&self) -> u64 {
+        return self.display_memory.iter()
+            .fold(0xCBF2_9CE4_8422_2325, |hash, &p| (hash ^ p as u64).wrapping_mul(0x0100_0000_01B3));
+    }
+
+    pub fn set_display(&mut self, pixels: &[bool]) -> Result<(), Chip8Error> {
+        if pixels.len() != self.display_memory.len() {
+            return Err(Chip8Error::DisplaySizeMismatch { size : pixels.len(), expected : self.display_memory.len() });
+        }
+        self.display_memory.copy_from_slice(pixels);
+        self.mark_all_dirty();
+        return Ok(());
+    }
+
+    pub fn display_matches(&self, expected: &[bool]) -> bool {
+        return self.display_memory == expected;
+    }
+
+    // Calls `callback` with the display only if an instruction changed it
+    // since the last call, so embedders can redraw exactly when needed.
+    pub fn on_draw(&mut self, mut callback: impl FnMut(&[bool])) {
+        if self.display_dirty {
+            self.display_dirty = false;
+            callback(&self.display_memory);
+        }
+    }
+}
+
+// Progress of a blocked Fx0A.
+#[derive(Clone, Copy, Default)]
+struct KeyWait {
+    // A fresh press waiting for its release.
+    pressed: Option<u8>,
+    // Times Fx0A has run without the wait ending.
+    cycles: u64,
+    // How much of `key_events` the wait has already read.
+    seen: usize,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum KeyEvent {
+    Down(u8),
+    Up(u8),
+}
+
+// All quirks off means the original COSMAC VIP behavior.
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub struct Quirks {
+    // 8xy6/8xyE shift Vx in place instead of loading Vy shifted.
+    pub shift: bool,
+    // Fx55/Fx65 leave I unchanged instead of advancing it past the last register.
+    pub load_store: bool,
+    // Non-standard: Fx55/Fx65 clear VF once done. No interpreter is
+    // known to do this, the VIP leaves VF alone unless it was loaded; it's
+    // an escape hatch for ROMs that only ran on something that did.
+    pub load_store_clears_vf: bool,
+    // Fx1E sets VF when I goes past 0x0FFF (Amiga interpreter).
+    pub add_i: bool,
+    // Non-standard: 7xkk sets VF on carry. No real interpreter should do
+    // this, it only exists for experimenting with ROMs built on buggy ones.
+    pub add_val_sets_vf: bool,
+    // Fx0A completes as soon as a key goes down, not when it's released.
+    // Named for the non-VIP behavior so that, like every other quirk,
+    // off is what the VIP does.
+    pub fx0a_on_press: bool,
+    // Dxyn clamps a starting position past the edge to the edge
+    // instead of wrapping it around.
+    pub clamp_origin: bool,
+    // Dxyn wraps the sprite's pixels around the edges instead of
+    // clipping them.
+    pub wrap_sprites: bool,
+    // Legacy SCHIP 1.1 scrolls in hires pixels even in lores mode, so
+    // 00CN, 00FB and 00FC move lores content by half as much.
+    pub half_lores_scroll: bool,
+    // Enables the SUPER-CHIP extensions.
+    pub schip: bool,
+    // Enables the XO-CHIP extensions.
+    pub xochip: bool,
+}
+
+// The quirks that can be flipped on a running machine. The extensions
+// aren't among them, XO-CHIP changes the memory size.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum QuirkKind {
+    Shift,
+    LoadStore,
+    LoadStoreClearsVf,
+    AddI,
+    AddValSetsVf,
+    Fx0aOnPress,
+    ClampOrigin,
+    WrapSprites,
+    HalfLoresScroll,
+}
+
+impl QuirkKind {
+    pub fn from_name(name: &str) -> Option<QuirkKind> {
+        return match name {
+            "shift" => Some(QuirkKind::Shift),
+            "load_store" => Some(QuirkKind::LoadStore),
+            "load_store_clears_vf" => Some(QuirkKind::LoadStoreClearsVf),
+            "add_i" => Some(QuirkKind::AddI),
+            "add_val_sets_vf" => Some(QuirkKind::AddValSetsVf),
+            "fx0a_on_press" => Some(QuirkKind::Fx0aOnPress),
+            "clamp_origin" => Some(QuirkKind::ClampOrigin),
+            "wrap_sprites" => Some(QuirkKind::WrapSprites),
+            "half_lores_scroll" => Some(QuirkKind::HalfLoresScroll),
+            _ => None,
+        };
+    }
+}
+
+// What Dxyn's sprite rows and Fx33's digits do past the end of memory.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum MemoryBounds {
+    // Carry on from the start of memory, as the address lines wrap.
+    Wrap,
+    // Only use the bytes that fit, with a warning.
+    Clamp,
+    // Fail with MemoryOutOfBounds.
+    Error,
+}
+
+// How long a blocked Fx0A may wait, in instructions, so headless runs
+// without input don't hang on it forever.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct KeyWaitTimeout {
+    pub cycles: u64,
+    pub action: KeyWaitAction,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum KeyWaitAction {
+    // Fail with WaitTimedOut.
+    Abort,
+    // Carry on as if this key had been pressed and released.
+    PressKey(u8),
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct FrameResult {
+    // The display changed, so it needs redrawing.
+    pub drew: bool,
+    pub sound: bool,
+}
+
+// A region of the display in pixels, for backends that only upload
+// what changed. A sprite wrapping around an edge dirties the whole
+// width or height it wrapped across.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct DirtyRect {
+    pub x: usize,
+    pub y: usize,
+    pub width: usize,
+    pub height: usize,
+}
+
+impl DirtyRect {
+    // The smallest rectangle covering both.
+    pub fn union(self, other: DirtyRect) -> DirtyRect {
+        let (x, y) = (self.x.min(other.x), self.y.min(other.y));
+        return DirtyRect {
+            x,
+            y,
+            width : (self.x + self.width).max(other.x + other.width) - x,
+            height : (self.y + self.height).max(other.y + other.height) - y,
+        };
+    }
+}
+
+// One Dxyn, collected when `log_draws` is on.
+#[derive(Clone, Debug, PartialEq)]
+pub struct DrawEvent {
+    pub pc: u16,
+    pub x: u8,
+    pub y: u8,
+    pub height: u8,
+    // Pixels turned off, over all the planes drawn to.
+    pub collisions: usize,
+    pub vf: u8,
+}
+
+impl fmt::Display for DrawEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        return write!(f, "{:03X}: sprite at ({}, {}), {} rows high, {} pixels collided, VF={}",
+            self.pc, self.x, self.y, self.height, self.collisions, self.vf);
+    }
+}
+
+// Something that went wrong without stopping the program.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Warning {
+    OddRom { len: usize },
+    // Under MemoryBounds::Clamp, a sprite drawn with only `rows` of its
+    // `height` inside memory.
+    SpriteClamped { addr: usize, rows: usize, height: usize },
+    // Under MemoryBounds::Clamp, an Fx33 with room for only `digits` of
+    // its three.
+    BcdClamped { addr: usize, digits: usize },
+}
+
+impl fmt::Display for Warning {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        return match self {
+            Warning::OddRom { len } => write!(f, "the rom is {} bytes, an odd length", len),
+            Warning::SpriteClamped { addr, rows, height } =>
+                write!(f, "the sprite at {:03X} runs past the end of memory, drawing {} of {} rows", addr, rows, height),
+            Warning::BcdClamped { addr, digits } =>
+                write!(f, "the BCD at {:03X} runs past the end of memory, writing {} of 3 digits", addr, digits),
+        };
+    }
+}
+
+// Places where the program would behave differently under another
+// quirk setting, collected when `strict_quirks` is on.
+#[derive(Clone, Debug)]
+pub enum QuirkEvent {
+    Shift { pc: u16, x: u8, y: u8 },
+    LoadStore { pc: u16, read_at: u16 },
+    EmptySprite { pc: u16 },
+}
+
+impl fmt::Display for QuirkEvent {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        return match self {
+            QuirkEvent::Shift { pc, x, y } =>
+                write!(f, "{:03X}: shift with V{:X} != V{:X} depends on the shift quirk", pc, x, y),
+            QuirkEvent::LoadStore { pc, read_at } =>
+                write!(f, "{:03X}: I read at {:03X} depends on the load/store quirk", pc, read_at),
+            QuirkEvent::EmptySprite { pc } =>
+                write!(f, "{:03X}: Dxy0 draws nothing without SCHIP or XO-CHIP", pc),
+        };
+    }
+}
+
+// The built-in 4x5 hex digit font, at the usual base below the program area.
+pub const FONT_BASE: usize = 0x50;
+const FONT: [u8; 80] = [
+    0xF0, 0x90, 0x90, 0x90, 0xF0, // 0
+    0x20, 0x60, 0x20, 0x20, 0x70, // 1
+    0xF0, 0x10, 0xF0, 0x80, 0xF0, // 2
+    0xF0, 0x10, 0xF0, 0x10, 0xF0, // 3
+    0x90, 0x90, 0xF0, 0x10, 0x10, // 4
+    0xF0, 0x80, 0xF0, 0x10, 0xF0, // 5
+    0xF0, 0x80, 0xF0, 0x90, 0xF0, // 6
+    0xF0, 0x10, 0x20, 0x40, 0x40, // 7
+    0xF0, 0x90, 0xF0, 0x90, 0xF0, // 8
+    0xF0, 0x90, 0xF0, 0x10, 0xF0, // 9
+    0xF0, 0x90, 0xF0, 0x90, 0x90, // A
+    0xE0, 0x90, 0xE0, 0x90, 0xE0, // B
+    0xF0, 0x80, 0x80, 0x80, 0xF0, // C
+    0xE0, 0x90, 0x90, 0x90, 0xE0, // D
+    0xF0, 0x80, 0xF0, 0x80, 0xF0, // E
+    0xF0, 0x80, 0xF0, 0x80, 0x80, // F
+];
+
+// The 8x10 digits SCHIP's Fx30 points at, right after the small font.
+// SCHIP only had 0-9, these are Octo's, which go up to F.
+pub const BIG_FONT_BASE: usize = FONT_BASE + 80;
+const BIG_FONT: [u8; 160] = [
+    0xFF, 0xFF, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, // 0
+    0x18, 0x78, 0x78, 0x18, 0x18, 0x18, 0x18, 0x18, 0xFF, 0xFF, // 1
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // 2
+    0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 3
+    0xC3, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0x03, 0x03, // 4
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 5
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 6
+    0xFF, 0xFF, 0x03, 0x03, 0x06, 0x0C, 0x18, 0x18, 0x18, 0x18, // 7
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, // 8
+    0xFF, 0xFF, 0xC3, 0xC3, 0xFF, 0xFF, 0x03, 0x03, 0xFF, 0xFF, // 9
+    0x7E, 0xFF, 0xC3, 0xC3, 0xC3, 0xFF, 0xFF, 0xC3, 0xC3, 0xC3, // A
+    0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, 0xC3, 0xC3, 0xFC, 0xFC, // B
+    0x3C, 0xFF, 0xC3, 0xC0, 0xC0, 0xC0, 0xC0, 0xC3, 0xFF, 0x3C, // C
+    0xFC, 0xFE, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xC3, 0xFE, 0xFC, // D
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, // E
+    0xFF, 0xFF, 0xC0, 0xC0, 0xFF, 0xFF, 0xC0, 0xC0, 0xC0, 0xC0, // F
+];
+
+// The glyphs Fx29 and Fx30 point at, 16 of 5 and 16 of 10 bytes.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Font {
+    small: [u8; 80],
+    big: [u8; 160],
+}
+
+impl Font {
+    // Without a big font the built-in one is kept.
+    pub fn new(small: &[u8], big: Option<&[u8]>) -> Result<Font, Chip8Error> {
+        let mut font = Font::default();
+        if small.len() != font.small.len() {
+            return Err(Chip8Error::FontSize { size : small.len(), expected : font.small.len() });
+        }
+        font.small.copy_from_slice(small);
+        if let Some(big) = big {
+            if big.len() != font.big.len() {
+                return Err(Chip8Error::FontSize { size : big.len(), expected : font.big.len() });
+            }
+            font.big.copy_from_slice(big);
+        }
+        return Ok(font);
+    }
+}
+
+impl Default for Font {
+    fn default() -> Font {
+        return Font { small : FONT, big : BIG_FONT };
+    }
+}
+
+pub struct Chip8Builder {
+    load_address: u16,
+    quirks: Quirks,
+    auto_quirks: bool,
+    strict_quirks: bool,
+    enforce_alignment: bool,
+    memory_bounds: MemoryBounds,
+    key_wait_timeout: Option<KeyWaitTimeout>,
+    font: Font,
+    log_draws: bool,
+    track_memory: bool,
+    crash_trace: usize,
+    instructions_per_frame: u32,
+    seed: Option<u64>,
+}
+
+impl Chip8Builder {
+    pub fn new() -> Chip8Builder {
+        Chip8Builder {
+            load_address : 0x200,
+            quirks : Quirks::default(),
+            auto_quirks : true,
+            strict_quirks : false,
+            enforce_alignment : false,
+            memory_bounds : MemoryBounds::Wrap,
+            key_wait_timeout : None,
+            font : Font::default(),
+            log_draws : false,
+            track_memory : false,
+            crash_trace : 0,
+            instructions_per_frame : 10,
+            seed : None,
+        }
+    }
+
+    // ETI-660 programs load and start at 0x600.
+    pub fn load_address(self, load_address: u16) -> Chip8Builder {
+        Chip8Builder { load_address, .. self }
+    }
+
+    // Explicit quirks override the profile looked up by ROM hash.
+    pub fn quirks(self, quirks: Quirks) -> Chip8Builder {
+        Chip8Builder { quirks, auto_quirks : false, .. self }
+    }
+
+    pub fn strict_quirks(self, strict_quirks: bool) -> Chip8Builder {
+        Chip8Builder { strict_quirks, .. self }
+    }
+
+    // Fail on an odd PC, which usually means a bad jump has desynced decoding.
+    pub fn enforce_alignment(self, enforce_alignment: bool) -> Chip8Builder {
+        Chip8Builder { enforce_alignment, .. self }
+    }
+
+    pub fn memory_bounds(self, memory_bounds: MemoryBounds) -> Chip8Builder {
+        Chip8Builder { memory_bounds, .. self }
+    }
+
+    // Without a timeout Fx0A waits as long as it takes.
+    pub fn key_wait_timeout(self, timeout: KeyWaitTimeout) -> Chip8Builder {
+        Chip8Builder { key_wait_timeout : Some(timeout), .. self }
+    }
+
+    pub fn font(self, font: Font) -> Chip8Builder {
+        Chip8Builder { font, .. self }
+    }
+
+    // How many instructions `step_frame` runs.
+    pub fn instructions_per_frame(self, instructions_per_frame: u32) -> Chip8Builder {
+        Chip8Builder { instructions_per_frame, .. self }
+    }
+
+    // Keep a DrawEvent for every Dxyn, for debugging collisions.
+    pub fn log_draws(self, log_draws: bool) -> Chip8Builder {
+        Chip8Builder { log_draws, .. self }
+    }
+
+    // Count memory reads and writes for a heatmap, see `memory_heat`.
+    pub fn track_memory(self, track_memory: bool) -> Chip8Builder {
+        Chip8Builder { track_memory, .. self }
+    }
+
+    // Keep the trace of the last `lines` instructions, see `post_mortem`.
+    pub fn crash_trace(self, lines: usize) -> Chip8Builder {
+        Chip8Builder { crash_trace : lines, .. self }
+    }
+
+    // Without a seed Cxkk is seeded from entropy.
+    pub fn seed(self, seed: u64) -> Chip8Builder {
+        Chip8Builder { seed : Some(seed), .. self }
+    }
+
+    pub fn build(self) -> Chip8 {
+        let mut memory = vec![0; memory_size(&self.quirks)];
+        memory[FONT_BASE..(FONT_BASE + 80)].copy_from_slice(&self.font.small);
+        memory[BIG_FONT_BASE..(BIG_FONT_BASE + 160)].copy_from_slice(&self.font.big);
+        Chip8 {
+            memory,
+            mem_mask : (memory_size(&self.quirks) - 1) as u16,
+            v : vec![0; 16],
+            reg_i : 0,
+            program_counter : self.load_address,
+            stack : [0; 16],
+            sp : 0,
+            delay_timer : 0,
+            sound_timer : 0,
+            keyboard : [false; 16],
+            key_input : [false; 16],
+            key_events : Vec::new(),
+            key_wait : None,
+            display_memory : vec![false; 64 * 32],
+            plane2 : vec![false; 64 * 32],
+            selected_planes : 1,
+            hires : false,
+            display_dirty : false,
+            frame_drew : false,
+            dirty_rect : None,
+            rng : rng::new_rng(self.seed),
+            seed : self.seed,
+            load_address : self.load_address,
+            quirks : self.quirks,
+            auto_quirks : self.auto_quirks,
+            strict_quirks : self.strict_quirks,
+            enforce_alignment : self.enforce_alignment,
+            memory_bounds : self.memory_bounds,
+            key_wait_timeout : self.key_wait_timeout,
+            font : self.font,
+            log_draws : self.log_draws,
+            instructions_per_frame : self.instructions_per_frame,
+            draw_log : Vec::new(),
+            warnings : Vec::new(),
+            quirk_log : Vec::new(),
+            pending_load_store : None,
+            report : report::Report::default(),
+            heat : if self.track_memory { Some(heatmap::MemoryHeat::new()) } else { None },
+            crash_trace : self.crash_trace,
+            recent_trace : VecDeque::with_capacity(self.crash_trace),
+            freeze_timers : false,
+            unfocused : None,
+            pattern_buffer : [0; 16],
+            pitch : 64,
+        }
+    }
+}
+
+impl Default for Chip8Builder {
+    fn default() -> Chip8Builder {
+        return Chip8Builder::new();
+    }
+}
+
+#[derive(Debug)]
+pub enum Chip8Error {
+    Io(io::Error),
+    RomTooLarge { size: usize, max: usize },
+    StackOverflow { pc: u16 },
+    StackUnderflow { pc: u16 },
+    MemoryOutOfBounds { addr: usize },
+    MisalignedPc { pc: u16 },
+    InvalidState(&'static str),
+    SelfTestFailed(&'static str),
+    NoStackEntry { depth: usize },
+    DisplaySizeMismatch { size: usize, expected: usize },
+    FontSize { size: usize, expected: usize },
+    Network(String),
+    WaitTimedOut { pc: u16 },
+    OperandOutOfRange { value: u16, max: u16 },
+}
+
+impl fmt::Display for Chip8Error {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        return match self {
+            Chip8Error::Io(e) => write!(f, "Couldn't load the rom: {}", e),
+            Chip8Error::RomTooLarge { size, max } =>
+                write!(f, "The rom is {} bytes, but only {} fit in memory.", size, max),
+            Chip8Error::StackOverflow { pc } =>
+                write!(f, "Stack overflow at {:03X}: more than 16 nested calls.", pc),
+            Chip8Error::StackUnderflow { pc } =>
+                write!(f, "Stack underflow at {:03X}: return without a call.", pc),
+            Chip8Error::MemoryOutOfBounds { addr } =>
+                write!(f, "Address {:03X} is outside of memory.", addr),
+            Chip8Error::MisalignedPc { pc } =>
+                write!(f, "The program counter is at an odd address, {:03X}.", pc),
+            Chip8Error::DisplaySizeMismatch { size, expected } =>
+                write!(f, "Got {} pixels for a display of {}.", size, expected),
+            Chip8Error::InvalidState(reason) =>
+                write!(f, "Couldn't load the state: {}", reason),
+            Chip8Error::SelfTestFailed(reason) => write!(f, "The self-test failed: {}", reason),
+            Chip8Error::NoStackEntry { depth } => write!(f, "There's no call at stack depth {}.", depth),
+            Chip8Error::FontSize { size, expected } =>
+                write!(f, "Got a {} byte font, expected {} bytes.", size, expected),
+            Chip8Error::Network(reason) => write!(f, "Couldn't download the rom: {}", reason),
+            Chip8Error::WaitTimedOut { pc } =>
+                write!(f, "Gave up waiting for a key at {:03X}.", pc),
+            Chip8Error::OperandOutOfRange { value, max } =>
+                write!(f, "The operand {:X} is larger than {:X}.", value, max),
+        };
+    }
+}
+
+impl From<io::Error> for Chip8Error {
+    fn from(e: io::Error) -> Chip8Error {
+        return Chip8Error::Io(e);
+    }
+}
+
+#[derive(Debug)]
+pub enum MetaOpcode {
+    FlowControl(FlowControlOpcode),
+    Regular(RegularOpcode),
+    Unknown(u16),
+}
+use MetaOpcode::*;
+
+#[derive(Debug)]
+pub enum FlowControlOpcode {
+    Jump { addr: u16 },
+    JumpPlusV0 { addr: u16 },
+    Call { addr: u16 },
+    Return,
+}
+use FlowControlOpcode::*;
+
+#[derive(Debug)]
+pub enum RegularOpcode {
+    SysCall { addr: u16 },
+    SkipIfRegValEqual { x: u8, value: u8 },
+    SkipIfRegValNotEqual { x: u8, value: u8 },
+    SkipIfRegRegEqual { x: u8, y: u8 },
+    SkipIfRegRegNotEqual { x: u8, y: u8 },
+    SkipIfKeyPressed { x: u8 },
+    SkipIfKeyNotPressed { x: u8 },
+
+    LoadValToReg { x: u8, value: u8 }, 
+    LoadRegToReg { x: u8, y: u8 }, 
+    LoadDelayTimerToReg { x: u8 },
+    LoadKeyToReg { x: u8 },
+    LoadRegToDelayTimer { x: u8 },
+    LoadRegToSoundTimer { x: u8 },
+    LoadValToI { value: u16 },
+    LoadSpriteLocationToI { x: u8 }, 
+    LoadBigSpriteLocationToI { x: u8 },
+    LoadRegBcdToMem { x: u8 },
+    LoadRegsToMem { n: u8 },
+    LoadMemToRegs { n: u8 },
+    LoadRandomAndValToReg { x: u8, value: u8 },
+    LoadPatternBuffer,
+    LoadRegToPitch { x: u8 },
+
+    SubRegFromReg { x: u8, y: u8 }, 
+    SubnRegFromReg { x: u8, y: u8 }, 
+    AddValToReg { x: u8, value: u8 },
+    AddRegToI { x: u8 },
+    OrRegReg { x: u8, y: u8 },
+    AndRegReg { x: u8, y: u8 },
+    XorRegReg { x: u8, y: u8 },
+    AddRegToReg { x: u8, y: u8 },
+    ShiftRightReg { x: u8, y: u8 },
+    ShiftLeftReg { x: u8, y: u8 },
+
+    ClearScreen,
+    ScrollDown { n: u8 },
+    ScrollUp { n: u8 },
+    ScrollRight,
+    ScrollLeft,
+    LowRes,
+    HighRes,
+    SelectPlanes { planes: u8 },
+    DrawSprite { x: u8, y: u8, n: u8 },
+}
+use RegularOpcode::*;
+
+fn operand(value: u16, max: u16) -> Result<u8, Chip8Error> {
+    if value > max {
+        return Err(Chip8Error::OperandOutOfRange { value, max });
+    }
+    return Ok(value as u8);
+}
+
+// Checked constructors for the instructions taking a register and a
+// byte (3xkk, 4xkk, 6xkk, 7xkk and Cxkk), for building opcodes from
+// wider values, e.g. ones parsed from assembly. A register past VF or
+// a value past FF is an error rather than silently truncated.
+impl RegularOpcode {
+    pub fn skip_if_reg_val_equal(x: u16, value: u16) -> Result<RegularOpcode, Chip8Error> {
+        return Ok(SkipIfRegValEqual { x : operand(x, 0xF)?, value : operand(value, 0xFF)? });
+    }
+
+    pub fn skip_if_reg_val_not_equal(x: u16, value: u16) -> Result<RegularOpcode, Chip8Error> {
+        return Ok(SkipIfRegValNotEqual { x : operand(x, 0xF)?, value : operand(value, 0xFF)? });
+    }
+
+    pub fn load_val_to_reg(x: u16, value: u16) -> Result<RegularOpcode, Chip8Error> {
+        return Ok(LoadValToReg { x : operand(x, 0xF)?, value : operand(value, 0xFF)? });
+    }
+
+    pub fn add_val_to_reg(x: u16, value: u16) -> Result<RegularOpcode, Chip8Error> {
+        return Ok(AddValToReg { x : operand(x, 0xF)?, value : operand(value, 0xFF)? });
+    }
+
+    pub fn load_random_and_val_to_reg(x: u16, value: u16) -> Result<RegularOpcode, Chip8Error> {
+        return Ok(LoadRandomAndValToReg { x : operand(x, 0xF)?, value : operand(value, 0xFF)? });
+    }
+}
+
+// Every instruction `step` implements. The SCHIP and XO-CHIP ones
+// only run with their quirk enabled.
+pub fn supported_opcodes() -> Vec<&'static str> {
+    return vec![
+        "0nnn", "00E0", "00EE", "00Cn", "00Dn", "00FB", "00FC", "00FE", "00FF",
+        "1nnn", "2nnn", "3xkk", "4xkk", "5xy0", "6xkk", "7xkk",
+        "8xy0", "8xy1", "8xy2", "8xy3", "8xy4", "8xy5", "8xy6", "8xy7", "8xyE",
+        "9xy0", "Annn", "Bnnn", "Cxkk", "Dxyn", "Ex9E", "ExA1",
+        "Fn01", "F002", "Fx07", "Fx0A", "Fx15", "Fx18", "Fx1E", "Fx29", "Fx30", "Fx33", "Fx3A", "Fx55", "Fx65",
+    ];
+}
+
+pub fn parse_opcode(opcode: u16) -> MetaOpcode {
+    let a = (0x000F & (opcode >> 12)) as u8;
+    let b = (0x000F & (opcode >>  8)) as u8;
+    let c = (0x000F & (opcode >>  4)) as u8;
+    let d = (0x000F & (opcode >>  0)) as u8;
+
+    let nnn = 0x0FFF & opcode;
+    let kk = (0x00FF & opcode) as u8;
+
+    return match (a, b, c, d) {
+        (  0,   0, 0xE,   0) => Regular(ClearScreen),
+        (  0,   0, 0xE, 0xE) => FlowControl(Return),
+        (  0,   0, 0xC,   n) => Regular(ScrollDown { n }),
+        (  0,   0, 0xD,   n) => Regular(ScrollUp { n }),
+        (  0,   0, 0xF, 0xB) => Regular(ScrollRight),
+        (  0,   0, 0xF, 0xC) => Regular(ScrollLeft),
+        (  0,   0, 0xF, 0xE) => Regular(LowRes),
+        (  0,   0, 0xF, 0xF) => Regular(HighRes),
+        (  0,   _,   _,   _) => Regular(SysCall { addr : nnn }),
+        (  1,   _,   _,   _) => FlowControl(Jump { addr : nnn }),
+        (  2,   _,   _,   _) => FlowControl(Call { addr : nnn }),
+        (  3,   x,   _,   _) => Regular(SkipIfRegValEqual { x, value : kk }),
+        (  4,   x,   _,   _) => Regular(SkipIfRegValNotEqual { x, value : kk }),
+        (  5,   x,   y,   0) => Regular(SkipIfRegRegEqual { x, y }),
+        (  6,   x,   _,   _) => Regular(LoadValToReg { x, value : kk }),
+        (  7,   x,   _,   _) => Regular(AddValToReg { x, value : kk }),
+        (  8,   x,   y,   0) => Regular(LoadRegToReg { x, y }),
+        (  8,   x,   y,   1) => Regular(OrRegReg { x, y }),
+        (  8,   x,   y,   2) => Regular(AndRegReg { x, y }),
+        (  8,   x,   y,   3) => Regular(XorRegReg { x, y }),
+        (  8,   x,   y,   4) => Regular(AddRegToReg { x, y }),
+        (  8,   x,   y,   5) => Regular(SubRegFromReg { x, y }),
+        (  8,   x,   y,   6) => Regular(ShiftRightReg { x, y }),
+        (  8,   x,   y,   7) => Regular(SubnRegFromReg { x, y }),
+        (  8,   x,   y, 0xE) => Regular(ShiftLeftReg { x, y }),
+        (  9,   x,   y,   0) => Regular(SkipIfRegRegNotEqual { x, y }),
+        (0xA,   _,   _,   _) => Regular(LoadValToI { value : nnn }),
+        (0xB,   _,   _,   _) => FlowControl(JumpPlusV0 { addr : nnn }),
+        (0xC,   x,   _,   _) => Regular(LoadRandomAndValToReg { x, value : kk }),
+        (0xD,   x,   y,   n) => Regular(DrawSprite { x, y, n }),
+        (0xE,   x,   9, 0xE) => Regular(SkipIfKeyPressed { x }),
+        (0xE,   x, 0xA,   1) => Regular(SkipIfKeyNotPressed { x }),
+        (0xF,   n,   0,   1) => Regular(SelectPlanes { planes : n }),
+        (0xF,   0,   0,   2) => Regular(LoadPatternBuffer),
+        (0xF,   x,   0,   7) => Regular(LoadDelayTimerToReg { x }),
+        (0xF,   x,   0, 0xA) => Regular(LoadKeyToReg { x }),
+        (0xF,   x,   1,   5) => Regular(LoadRegToDelayTimer { x }),
+        (0xF,   x,   1,   8) => Regular(LoadRegToSoundTimer { x }),
+        (0xF,   x,   1, 0xE) => Regular(AddRegToI { x }),
+        (0xF,   x,   2,   9) => Regular(LoadSpriteLocationToI { x }),
+        (0xF,   x,   3,   0) => Regular(LoadBigSpriteLocationToI { x }),
+        (0xF,   x,   3,   3) => Regular(LoadRegBcdToMem { x }),
+        (0xF,   x,   3, 0xA) => Regular(LoadRegToPitch { x }),
+        (0xF,   n,   5,   5) => Regular(LoadRegsToMem { n }),
+        (0xF,   n,   6,   5) => Regular(LoadMemToRegs { n }),
+        (  _,   _,   _,   _) => Unknown(opcode)
+    }
+}
+
+pub fn cycle_cost(opcode: &MetaOpcode) -> u32 {
+    return match opcode {
+        Regular(ClearScreen) => 8,
+        Regular(DrawSprite { n, .. }) => 4 + *n as u32,
+        Regular(LoadRegBcdToMem { .. }) => 4,
+        Regular(LoadRegsToMem { n }) => 1 + *n as u32,
+        Regular(LoadMemToRegs { n }) => 1 + *n as u32,
+        Regular(LoadRandomAndValToReg { .. }) => 2,
+        FlowControl(Call { .. }) => 2,
+        FlowControl(Return) => 2,
+        _ => 1,
+    }
+}
+
+// How long instructions take when pacing the main loop.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Timing {
+    // `cycle_cost` units, with a fixed budget of them per frame.
+    Fixed,
+    // Microseconds on the COSMAC VIP, out of VIP_FRAME_MICROS a frame.
+    CosmacVip,
+}
+
+pub const VIP_FRAME_MICROS: u32 = 16667;
+
+impl Timing {
+    pub fn cost(self, opcode: &MetaOpcode) -> u32 {
+        return match self {
+            Timing::Fixed => cycle_cost(opcode),
+            Timing::CosmacVip => vip_cost(opcode),
+        };
+    }
+}
+
+// Average VIP interpreter times, as measured in "CHIP-8 Instruction
+// Scheduling and Frequency" (Jackson S., 2019). DRW's includes waiting
+// for the display interrupt, so under this timing it ends the frame.
+// The extensions never ran on a VIP and cost as little as anything did.
+fn vip_cost(opcode: &MetaOpcode) -> u32 {
+    return match opcode {
+        Regular(ClearScreen) => 109,
+        Regular(SysCall { .. }) | FlowControl(_) => 105,
+        Regular(SkipIfRegValEqual { .. }) | Regular(SkipIfRegValNotEqual { .. }) => 55,
+        Regular(SkipIfRegRegEqual { .. }) | Regular(SkipIfRegRegNotEqual { .. }) => 73,
+        Regular(SkipIfKeyPressed { .. }) | Regular(SkipIfKeyNotPressed { .. }) => 73,
+        Regular(LoadValToReg { .. }) => 27,
+        Regular(AddValToReg { .. }) => 45,
+        Regular(LoadRegToReg { .. }) | Regular(OrRegReg { .. }) | Regular(AndRegReg { .. })
+        | Regular(XorRegReg { .. }) | Regular(AddRegToReg { .. }) | Regular(SubRegFromReg { .. })
+        | Regular(SubnRegFromReg { .. }) | Regular(ShiftRightReg { .. }) | Regular(ShiftLeftReg { .. }) => 200,
+        Regular(LoadValToI { .. }) => 55,
+        Regular(LoadRandomAndValToReg { .. }) => 164,
+        Regular(DrawSprite { .. }) => 22734,
+        Regular(LoadDelayTimerToReg { .. }) | Regular(LoadRegToDelayTimer { .. })
+        | Regular(LoadRegToSoundTimer { .. }) | Regular(LoadKeyToReg { .. }) => 45,
+        Regular(AddRegToI { .. }) => 86,
+        Regular(LoadSpriteLocationToI { .. }) => 91,
+        Regular(LoadRegBcdToMem { .. }) => 927,
+        Regular(LoadRegsToMem { .. }) | Regular(LoadMemToRegs { .. }) => 605,
+        _ => 27,
+    };
+}
+
+// XO-CHIP has 64K of memory, everything else 4K.
+pub fn memory_size(quirks: &Quirks) -> usize {
+    return if quirks.xochip { 0x10000 } else { 0x1000 };
+}
+
+// Machines can be driven either through `&mut self` methods or, in the
+// older functional style, through free functions taking the machine by
+// value and handing it back. These wrap the methods, so both behave the same.
+pub fn init() -> Chip8 {
+    return Chip8Builder::new().build();
+}
+
+pub fn load_rom_bytes(chip8: Chip8, rom: &[u8]) -> Result<Chip8, Chip8Error> {
+    let mut chip8 = chip8;
+    chip8.load_rom_bytes(rom)?;
+    return Ok(chip8);
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum ResetKind {
+    // Restarts the program with memory left as it is.
+    SoftKeepRom,
+    // A cold boot: memory is wiped and only the font reloaded.
+    HardClearAll,
+}
+
+// Either way the registers, timers, stack, keys and display start over
+// and the configuration is kept.
+pub fn reset(chip8: Chip8, kind: ResetKind) -> Chip8 {
+    let fresh = Chip8Builder {
+        load_address : chip8.load_address,
+        quirks : chip8.quirks,
+        auto_quirks : chip8.auto_quirks,
+        strict_quirks : chip8.strict_quirks,
+        enforce_alignment : chip8.enforce_alignment,
+        memory_bounds : chip8.memory_bounds,
+        key_wait_timeout : chip8.key_wait_timeout,
+        font : chip8.font,
+        log_draws : chip8.log_draws,
+        track_memory : chip8.heat.is_some(),
+        crash_trace : chip8.crash_trace,
+        instructions_per_frame : chip8.instructions_per_frame,
+        seed : chip8.seed,
+    }.build();
+    return match kind {
+        ResetKind::SoftKeepRom => Chip8 { memory : chip8.memory, mem_mask : chip8.mem_mask, .. fresh },
+        ResetKind::HardClearAll => fresh,
+    };
+}
+
+pub fn load_rom(chip8: Chip8, filename: &str) -> Result<Chip8, Chip8Error> {
+    let mut chip8 = chip8;
+    chip8.load_rom(filename)?;
+    return Ok(chip8);
+}
+
+pub fn load_rom_reader(chip8: Chip8, mut reader: impl Read) -> Result<Chip8, Chip8Error> {
+    let mut rom = Vec::new();
+    reader.read_to_end(&mut rom)?;
+    return load_rom_bytes(chip8, &rom);
+}
+
+// Nothing bigger than XO-CHIP memory can be a rom, so the download
+// stops one byte past that and load_rom_bytes rejects it.
+#[cfg(feature = "url")]
+pub fn load_rom_url(chip8: Chip8, url: &str) -> Result<Chip8, Chip8Error> {
+    let response = ureq::get(url).call().map_err(|e| Chip8Error::Network(e.to_string()))?;
+    return load_rom_reader(chip8, response.into_reader().take(0x10000 + 1));
+}
+
+// Limits on a headless run, so a rom that never halts can't hang CI.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Watchdog {
+    pub max_cycles: u64,
+    pub max_time: Option<Duration>,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum RunStatus {
+    // Reached a jump to itself, the usual way for a rom to stop.
+    Halted,
+    // Ran out of the watchdog's budget first.
+    TimedOut,
+    // An Fx0A ran out of its KeyWaitTimeout with Abort.
+    WaitTimedOut,
+}
+
+pub fn run_headless(chip8: Chip8, watchdog: Watchdog) -> Result<(Chip8, RunStatus), Chip8Error> {
+    let mut chip8 = chip8;
+    let start = Instant::now();
+    for cycle in 0..watchdog.max_cycles {
+        if jumps_to_itself(&chip8) {
+            return Ok((chip8, RunStatus::Halted));
+        }
+        // Reading the clock every instruction would dominate the run.
+        let out_of_time = watchdog.max_time.is_some_and(|max| cycle % 1024 == 0 && start.elapsed() > max);
+        if out_of_time {
+            return Ok((chip8, RunStatus::TimedOut));
+        }
+        match chip8.step() {
+            Err(Chip8Error::WaitTimedOut { .. }) => return Ok((chip8, RunStatus::WaitTimedOut)),
+            result => result?,
+        }
+    }
+    return Ok((chip8, RunStatus::TimedOut));
+}
+
+// Runs `rom` for at most `cycles` instructions with a fixed seed, applying
+// each (cycle, key, pressed) event right before that cycle's instruction.
+// `cycles` is the watchdog; the run also stops once the program jumps to
+// itself, since nothing after that changes.
+pub fn run_test_case(rom: &[u8], input_script: &[(usize, u8, bool)], cycles: usize, quirks: Quirks) -> Result<Chip8, Chip8Error> {
+    return run_script(rom, input_script, cycles, quirks, |_| ());
+}
+
+// The `trace_line` before every instruction of a `run_test_case` run.
+pub fn trace_test_case(rom: &[u8], input_script: &[(usize, u8, bool)], cycles: usize, quirks: Quirks) -> Result<Vec<String>, Chip8Error> {
+    let mut lines = Vec::new();
+    run_script(rom, input_script, cycles, quirks, |chip8| lines.push(chip8.trace_line()))?;
+    return Ok(lines);
+}
+
+// Builds the (cycle, key, pressed) events for `run_test_case`:
+// InputScript::new().at(10).press(0x5).at(25).release(0x5).build()
+// Presses and releases happen at the cycle of the last `at`.
+#[derive(Clone, Debug, Default)]
+pub struct InputScript {
+    cycle: usize,
+    events: Vec<(usize, u8, bool)>,
+}
+
+impl InputScript {
+    pub fn new() -> InputScript {
+        InputScript::default()
+    }
+
+    pub fn at(self, cycle: usize) -> InputScript {
+        InputScript { cycle, .. self }
+    }
+
+    pub fn press(self, key: u8) -> InputScript {
+        self.event(key, true)
+    }
+
+    pub fn release(self, key: u8) -> InputScript {
+        self.event(key, false)
+    }
+
+    fn event(self, key: u8, pressed: bool) -> InputScript {
+        let mut events = self.events;
+        events.push((self.cycle, key & 0xF, pressed));
+        InputScript { events, .. self }
+    }
+
+    // Ordered by cycle, events sharing one keep the order they were added in.
+    pub fn build(self) -> Vec<(usize, u8, bool)> {
+        let mut events = self.events;
+        events.sort_by_key(|event| event.0);
+        return events;
+    }
+}
+
+fn run_script(rom: &[u8], input_script: &[(usize, u8, bool)], cycles: usize, quirks: Quirks,
+              mut before_step: impl FnMut(&Chip8)) -> Result<Chip8, Chip8Error> {
+    let mut chip8 = load_rom_bytes(Chip8Builder::new().quirks(quirks).seed(0).build(), rom)?;
+    for cycle in 0..cycles {
+        if jumps_to_itself(&chip8) {
+            break;
+        }
+        for &(_, key, pressed) in input_script.iter().filter(|event| event.0 == cycle) {
+            chip8.set_key(key, pressed);
+        }
+        before_step(&chip8);
+        chip8 = step(chip8)?;
+    }
+    return Ok(chip8);
+}
+
+// The usual way for a rom to stop: the PC stays put from here on.
+fn jumps_to_itself(chip8: &Chip8) -> bool {
+    return matches!(chip8.peek_opcode(), Ok(FlowControl(Jump { addr })) if addr == chip8.program_counter);
+}
+
+// (bytes per row, rows) of the sprite Dxyn draws: 8 pixels wide and
+// n rows high, or 16x16 for n = 0 under SCHIP and XO-CHIP. Standard
+// CHIP-8 draws nothing for n = 0.
+fn sprite_shape(n: u8, quirks: &Quirks) -> Option<(usize, usize)> {
+    return match n {
+        0 if quirks.schip || quirks.xochip => Some((2, 16)),
+        0 => None,
+        n => Some((1, n as usize)),
+    };
+}
+
+// XORs `sprite` onto `plane`, returning how many rows turned a pixel
+// off, how many were clipped off the bottom, and how many pixels were
+// turned off.
+fn draw_sprite(plane: &mut [bool], width: usize, sprite: &[u8], row_bytes: usize, start_x: usize, start_y: usize,
+               quirks: &Quirks) -> (u8, u8, usize, Option<DirtyRect>) {
+    let height = plane.len() / width;
+    let (start_x, start_y) = if quirks.clamp_origin {
+        (start_x.min(width - 1), start_y.min(height - 1))
+    } else {
+        (start_x % width, start_y % height)
+    };
+    let mut rows = 0;
+    let mut clipped = 0;
+    let mut pixels = 0;
+    let mut dirty: Option<DirtyRect> = None;
+    for (yy, l) in sprite.chunks(row_bytes).map(|row| row.iter().flat_map(byte_to_bits)).enumerate() {
+        let y = start_y + yy;
+        if !quirks.wrap_sprites && y >= height {
+            clipped += 1;
+            continue;
+        }
+        let mut collision = false;
+        for (xx, pix) in l.enumerate() {
+            let x = start_x + xx;
+            if !quirks.wrap_sprites && x >= width {
+                continue;
+            }
+            let pos = x % width + (y % height) * width;
+            let cell = DirtyRect { x : x % width, y : y % height, width : 1, height : 1 };
+            dirty = Some(dirty.map_or(cell, |dirty| dirty.union(cell)));
+            let xored = plane[pos] ^ pix;
+            if plane[pos] && !xored {
+                collision = true;
+                pixels += 1;
+            }
+            plane[pos] = xored;
+        }
+        rows += collision as u8;
+    }
+    return (rows, clipped, pixels, dirty);
+}
+
+// Shifts `plane` by (dx, dy), filling the vacated area with off pixels.
+fn scroll(plane: &[bool], width: usize, dx: isize, dy: isize) -> Vec<bool> {
+    let (w, h) = (width as isize, (plane.len() / width) as isize);
+    let mut scrolled = vec![false; plane.len()];
+    for y in 0..h {
+        for x in 0..w {
+            let (src_x, src_y) = (x - dx, y - dy);
+            if (0..w).contains(&src_x) && (0..h).contains(&src_y) {
+                scrolled[(x + y * w) as usize] = plane[(src_x + src_y * w) as usize];
+            }
+        }
+    }
+    return scrolled;
+}
+
+// `memory` is public, so in case it was resized out from under the
+// mask, anything past its end reads as zero.
+fn read_memory(chip8: &Chip8, addr: usize, len: usize) -> Vec<u8> {
+    return (addr..(addr + len))
+        .map(|a| chip8.memory.get(a & chip8.mem_mask as usize).cloned().unwrap_or(0))
+        .collect();
+}
+
+// The hundreds, tens and ones digits Fx33 stores. A u8 never needs
+// more than three, so there's no overflow to handle.
+pub fn bcd(value: u8) -> [u8; 3] {
+    return [value / 100, value / 10 % 10, value % 10];
+}
+
+fn byte_to_bits(b: &u8) -> Vec<bool> {
+    return vec![
+        1u8 == (1u8 & (b >> 7)),
+        1u8 == (1u8 & (b >> 6)),
+        1u8 == (1u8 & (b >> 5)),
+        1u8 == (1u8 & (b >> 4)),
+        1u8 == (1u8 & (b >> 3)),
+        1u8 == (1u8 & (b >> 2)),
+        1u8 == (1u8 & (b >> 1)),
+        1u8 == (1u8 & (b >> 0)),
+    ];
+}
+
+fn fetch_checked(chip8: &Chip8) -> Result<u16, Chip8Error> {
+    let pc = chip8.program_counter as usize;
+    return match (chip8.memory.get(pc), chip8.memory.get(pc + 1)) {
+        (Some(&high), Some(&low)) => Ok((high as u16) << 8 | low as u16),
+        (Some(_), None) => Err(Chip8Error::MemoryOutOfBounds { addr : pc + 1 }),
+        (None, _) => Err(Chip8Error::MemoryOutOfBounds { addr : pc }),
+    };
+}
+
+// For display only: bytes past the end of memory read as 0, where
+// step fails with MemoryOutOfBounds instead.
+pub fn fetch_opcode(chip8: &Chip8) -> u16 {
+    let pc = chip8.program_counter as usize;
+    let byte = |addr: usize| chip8.memory.get(addr).cloned().unwrap_or(0) as u16;
+    return byte(pc) << 8 | byte(pc + 1);
+}
+
+pub fn disassemble(raw_opcode: u16) -> String {
+    return match parse_opcode(raw_opcode) {
+        FlowControl(opcode) => format!("{:04X} {:X?}", raw_opcode, opcode),
+        Regular(opcode) => format!("{:04X} {:X?}", raw_opcode, opcode),
+        unknown => format!("{:04X} {:X?}", raw_opcode, unknown)
+    };
+}
+
+fn check_quirks(chip8: &mut Chip8, opcode: &MetaOpcode) {
+    if !chip8.strict_quirks {
+        return;
+    }
+    let pc = chip8.program_counter;
+    let mut event = None;
+    let mut pending = chip8.pending_load_store;
+    match opcode {
+        Regular(ShiftRightReg { x, y }) | Regular(ShiftLeftReg { x, y })
+            if chip8.v[*x as usize] != chip8.v[*y as usize] =>
+                event = Some(QuirkEvent::Shift { pc, x : *x, y : *y }),
+        Regular(LoadValToI { .. }) | Regular(LoadSpriteLocationToI { .. })
+        | Regular(LoadBigSpriteLocationToI { .. }) => pending = None,
+        Regular(DrawSprite { n : 0, .. }) if sprite_shape(0, &chip8.quirks).is_none() =>
+            event = Some(QuirkEvent::EmptySprite { pc }),
+        Regular(DrawSprite { .. }) | Regular(LoadRegBcdToMem { .. }) | Regular(AddRegToI { .. })
+        | Regular(LoadPatternBuffer) | Regular(LoadRegsToMem { .. }) | Regular(LoadMemToRegs { .. }) => {
+            if let Some(load_store_pc) = pending {
+                event = Some(QuirkEvent::LoadStore { pc : load_store_pc, read_at : pc });
+            }
+            pending = match opcode {
+                Regular(LoadRegsToMem { .. }) | Regular(LoadMemToRegs { .. }) => Some(pc),
+                _ => None,
+            };
+        },
+        _ => (),
+    };
+    if let Some(e) = event {
+        chip8.report.quirk_sensitive = true;
+        chip8.quirk_log.push(e);
+    }
+    chip8.pending_load_store = pending;
+}
+
+pub fn step(chip8: Chip8) -> Result<Chip8, Chip8Error> {
+    let mut chip8 = chip8;
+    chip8.step()?;
+    return Ok(chip8);
+}
+
+// The display as rows of `#` and spaces, one line per row. The width
+// is told apart by the size: 128x64 in SCHIP hires, else 64x32.
+pub fn screen_text(display_memory: &[bool]) -> String {
+    let width = if display_memory.len() == 128 * 64 { 128 } else { 64 };
+    return display_memory.chunks(width)
+        .map(|row| row.iter().map(|&p| if p {'#'} else {' '}).collect::<String>() + "\n")
+        .collect();
+}
+
+pub fn display(display_memory: &[bool]) {
+    print!("{}", screen_text(display_memory));
+}
+
+pub fn dump_memory(chip8: &Chip8, addr: u16, len: u16) -> String {
+    let start = (addr as usize).min(chip8.memory.len());
+    let end = (start + len as usize).min(chip8.memory.len());
+    return chip8.memory[start..end]
+        .chunks(16)
+        .enumerate()
+        .map(|(i, row)| {
+            let bytes = row.iter().map(|b| format!("{:02X}", b)).collect::<Vec<String>>();
+            format!("{:03X}: {}", start + i * 16, bytes.join(" "))
+        })
+        .collect::<Vec<String>>()
+        .join("\n");
+}
+
+pub fn print_registers(chip8: &Chip8) {
+    println!("{}", registers_text(chip8));
+}
+
+fn registers_text(chip8: &Chip8) -> String {
+    return format!("PC:{:03X} I:{:03X} DT:{:02X} ST:{:02X}\nV:{:02X?}\nStack:{:03X?}",
+        chip8.program_counter, chip8.reg_i, chip8.delay_timer, chip8.sound_timer, chip8.v, chip8.stack());
+}
+
+pub fn diff(before: &Chip8, after: &Chip8) -> Vec<String> {
+    let mut changes = Vec::new();
+    for (i, (b, a)) in before.v.iter().zip(after.v.iter()).enumerate() {
+        if b != a {
+            changes.push(format!("V{:X} {:02X}->{:02X}", i, b, a));
+        }
+    }
+    if before.reg_i != after.reg_i {
+        changes.push(format!("I {:03X}->{:03X}", before.reg_i, after.reg_i));
+    }
+    if before.program_counter != after.program_counter {
+        changes.push(format!("PC {:03X}->{:03X}", before.program_counter, after.program_counter));
+    }
+    if before.delay_timer != after.delay_timer {
+        changes.push(format!("DT {:02X}->{:02X}", before.delay_timer, after.delay_timer));
+    }
+    if before.sound_timer != after.sound_timer {
+        changes.push(format!("ST {:02X}->{:02X}", before.sound_timer, after.sound_timer));
+    }
+    if before.sp != after.sp {
+        changes.push(format!("Stack depth {}->{}", before.sp, after.sp));
+    }
+    if before.display_memory != after.display_memory || before.plane2 != after.plane2 {
+        changes.push("Display changed".to_string());
+    }
+    return changes;
+}
+
+pub fn parse_address(s: &str) -> Option<u16> {
+    return match s.strip_prefix("0x") {
+        Some(hex) => u16::from_str_radix(hex, 16).ok(),
+        None => s.parse().ok(),
+    };
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // `rom` loaded at 0x200 on a seeded machine.
+    fn machine(rom: &[u8]) -> Chip8 {
+        let mut chip8 = Chip8Builder::new().seed(0).build();
+        chip8.load_rom_bytes(rom).unwrap();
+        return chip8;
+    }
+
+    #[test]
+    fn draw_costs_more_than_load() {
+        assert!(cycle_cost(&parse_opcode(0xD015)) > cycle_cost(&parse_opcode(0x6001)));
+        assert!(cycle_cost(&parse_opcode(0x00E0)) > cycle_cost(&parse_opcode(0x6001)));
+    }
+
+    #[test]
+    fn diff_lists_only_what_changed() {
+        let before = machine(&[0x65, 0x04]);
+        let mut after = before.clone();
+        after.step().unwrap();
+        assert_eq!(diff(&before, &after), vec!["V5 00->04", "PC 200->202"]);
+        assert!(diff(&after, &after).is_empty());
+    }
+
+    #[test]
+    fn rom_loads_and_starts_at_the_load_address() {
+        let mut chip8 = Chip8Builder::new().load_address(0x600).build();
+        chip8.load_rom_bytes(&[0x12, 0x34, 0x56]).unwrap();
+        assert_eq!(chip8.pc(), 0x600);
+        assert_eq!(&chip8.memory[0x600..0x603], &[0x12, 0x34, 0x56]);
+        assert_eq!(chip8.memory[0x200], 0);
+    }
+
+    #[test]
+    fn rom_has_to_fit_after_the_load_address() {
+        let mut chip8 = Chip8Builder::new().load_address(0x600).build();
+        assert!(chip8.load_rom_bytes(&[0; 0xA00]).is_ok());
+        assert!(matches!(chip8.load_rom_bytes(&[0; 0xA01]), Err(Chip8Error::RomTooLarge { size : 0xA01, max : 0xA00 })));
+    }
+
+    #[test]
+    fn load_address_past_memory_is_an_error() {
+        let mut chip8 = Chip8Builder::new().load_address(0x2000).build();
+        assert!(matches!(chip8.load_rom_bytes(&[]), Err(Chip8Error::MemoryOutOfBounds { addr : 0x2000 })));
+        let mut chip8 = Chip8Builder::new().load_address(0x1000).build();
+        assert!(chip8.load_rom_bytes(&[]).is_err());
+    }
+
+    fn strict_machine(rom: &[u8]) -> Chip8 {
+        let mut chip8 = Chip8Builder::new().strict_quirks(true).build();
+        chip8.load_rom_bytes(rom).unwrap();
+        return chip8;
+    }
+
+    #[test]
+    fn shift_of_different_registers_is_quirk_sensitive() {
+        let mut chip8 = strict_machine(&[0x60, 0x01, 0x61, 0x02, 0x80, 0x16]);
+        for _ in 0..3 {
+            chip8.step().unwrap();
+        }
+        assert!(matches!(chip8.quirk_log(), [QuirkEvent::Shift { pc : 0x204, x : 0, y : 1 }]));
+    }
+
+    #[test]
+    fn shift_of_equal_registers_is_not_quirk_sensitive() {
+        let mut chip8 = strict_machine(&[0x60, 0x02, 0x61, 0x02, 0x80, 0x16]);
+        for _ in 0..3 {
+            chip8.step().unwrap();
+        }
+        assert!(chip8.quirk_log().is_empty());
+    }
+
+    #[test]
+    fn reading_i_after_a_load_store_is_quirk_sensitive() {
+        let mut chip8 = strict_machine(&[0xA3, 0x00, 0xF1, 0x55, 0xF0, 0x1E]);
+        for _ in 0..3 {
+            chip8.step().unwrap();
+        }
+        assert!(matches!(chip8.quirk_log(), [QuirkEvent::LoadStore { pc : 0x202, read_at : 0x204 }]));
+    }
+
+    #[test]
+    fn f002_copies_sixteen_bytes_from_i() {
+        let mut rom = vec![0xA2, 0x06, 0xF0, 0x02, 0xF1, 0x3A];
+        rom.extend(1..=17);
+        let mut chip8 = Chip8Builder::new().quirks(Quirks { xochip : true, .. Quirks::default() }).build();
+        chip8.load_rom_bytes(&rom).unwrap();
+        chip8.set_reg(1, 100);
+        for _ in 0..3 {
+            chip8.step().unwrap();
+        }
+        let expected: Vec<u8> = (1..=16).collect();
+        assert_eq!(&chip8.pattern_buffer[..], &expected[..]);
+        assert_eq!(chip8.pitch, 100);
+    }
+
+    #[test]
+    fn auto_quirks_apply_unless_overridden() {
+        let rom = include_bytes!("../roms/shift.rom");
+        let mut chip8 = Chip8Builder::new().build();
+        chip8.load_rom_bytes(rom).unwrap();
+        assert!(chip8.quirks().shift);
+        assert!(chip8.run_until_pc(0x20C, 10).unwrap());
+        assert_eq!(chip8.reg(0), 1);
+        let mut chip8 = Chip8Builder::new().quirks(Quirks::default()).build();
+        chip8.load_rom_bytes(rom).unwrap();
+        assert!(!chip8.quirks().shift);
+        assert!(chip8.run_until_pc(0x20C, 10).unwrap());
+        assert_eq!(chip8.reg(0), 4);
+    }
+
+    #[test]
+    fn on_draw_fires_once_per_change() {
+        let mut chip8 = machine(&[0xA2, 0x00, 0xD0, 0x05, 0x12, 0x04]);
+        let mut calls = 0;
+        for _ in 0..5 {
+            chip8.step().unwrap();
+            chip8.on_draw(|display| {
+                assert!(display[0]);
+                calls += 1;
+            });
+        }
+        assert_eq!(calls, 1);
+    }
+
+    #[test]
+    fn fx1e_crossing_0fff_in_standard_mode() {
+        let mut chip8 = Chip8Builder::new().quirks(Quirks { add_i : true, .. Quirks::default() }).build();
+        chip8.load_rom_bytes(&[0xAF, 0xFF, 0xF0, 0x1E]).unwrap();
+        chip8.set_reg(0, 2);
+        chip8.step().unwrap();
+        chip8.step().unwrap();
+        assert_eq!(chip8.i(), 0x001);
+        assert_eq!(chip8.reg(0xF), 1);
+    }
+
+    #[test]
+    fn fx1e_crossing_0fff_in_xochip_mode() {
+        let mut chip8 = Chip8Builder::new().quirks(Quirks { add_i : true, xochip : true, .. Quirks::default() }).build();
+        chip8.load_rom_bytes(&[0xAF, 0xFF, 0xF0, 0x1E]).unwrap();
+        chip8.set_reg(0, 2);
+        chip8.step().unwrap();
+        chip8.step().unwrap();
+        assert_eq!(chip8.i(), 0x1001);
+        assert_eq!(chip8.reg(0xF), 0);
+    }
+
+    #[test]
+    fn ex9e_skips_while_the_key_is_pressed() {
+        let rom = [0x60, 0x05, 0xE0, 0x9E];
+        let mut chip8 = machine(&rom);
+        chip8.set_key(5, true);
+        assert!(chip8.keys()[5]);
+        chip8.step().unwrap();
+        chip8.step().unwrap();
+        assert_eq!(chip8.pc(), 0x206);
+
+        let mut chip8 = machine(&rom);
+        chip8.step().unwrap();
+        chip8.step().unwrap();
+        assert_eq!(chip8.pc(), 0x204);
+    }
+
+    #[test]
+    fn trace_line_format() {
+        let mut chip8 = machine(&[0xA2, 0x2A]);
+        chip8.set_i(0x22A);
+        chip8.set_reg(1, 0x01);
+        chip8.set_reg(0xF, 0xFF);
+        chip8.delay_timer = 7;
+        assert_eq!(chip8.trace_line(),
+            "PC:0x0200 OP:A22A I:0x022A V0..VF:[00,01,00,00,00,00,00,00,00,00,00,00,00,00,00,FF] DT:7 ST:0");
+    }
+
+    #[test]
+    fn instances_run_independently() {
+        let mut a = Chip8Builder::new().seed(1).build();
+        a.load_rom_bytes(&[0x60, 0x11, 0xC1, 0xFF, 0xA2, 0x00, 0xD0, 0x15]).unwrap();
+        let mut b = Chip8Builder::new().seed(2).build();
+        b.load_rom_bytes(&[0x60, 0x22, 0xC1, 0xFF, 0xA2, 0x00, 0x00, 0xE0]).unwrap();
+        let mut solo = a.clone();
+        for _ in 0..4 {
+            a.step().unwrap();
+            b.step().unwrap();
+            solo.step().unwrap();
+        }
+        assert_eq!((a.reg(0), b.reg(0)), (0x11, 0x22));
+        assert_ne!(a.reg(1), b.reg(1));
+        assert_ne!(a.display_snapshot(), b.display_snapshot());
+        assert!(diff(&a, &solo).is_empty());
+    }
+
+    #[test]
+    fn nested_calls_return_in_order() {
+        let mut chip8 = machine(&[0x22, 0x04, 0x12, 0x02, 0x22, 0x08, 0x00, 0xEE, 0x00, 0xEE]);
+        chip8.step().unwrap();
+        chip8.step().unwrap();
+        assert_eq!(chip8.stack(), &[0x200, 0x204]);
+        assert_eq!(chip8.pc(), 0x208);
+        chip8.step().unwrap();
+        assert_eq!((chip8.pc(), chip8.stack_depth()), (0x206, 1));
+        chip8.step().unwrap();
+        assert_eq!((chip8.pc(), chip8.stack_depth()), (0x202, 0));
+    }
+
+    #[test]
+    fn seventeenth_call_overflows() {
+        let mut chip8 = machine(&[0x22, 0x00]);
+        for _ in 0..16 {
+            chip8.step().unwrap();
+        }
+        assert_eq!(chip8.stack_depth(), 16);
+        assert!(matches!(chip8.step(), Err(Chip8Error::StackOverflow { pc : 0x200 })));
+    }
+
+    #[test]
+    fn return_on_an_empty_stack_underflows() {
+        let mut chip8 = machine(&[0x00, 0xEE]);
+        assert!(matches!(chip8.step(), Err(Chip8Error::StackUnderflow { pc : 0x200 })));
+    }
+
+    #[test]
+    fn peek_opcode_has_no_side_effects() {
+        let chip8 = machine(&[0x6A, 0x42]);
+        assert!(matches!(chip8.peek_opcode(), Ok(Regular(LoadValToReg { x : 0xA, value : 0x42 }))));
+        assert_eq!(chip8.pc(), 0x200);
+        assert_eq!(chip8.reg(0xA), 0);
+    }
+
+    #[test]
+    fn run_until_pc_stops_at_the_target() {
+        // Counts V0 up to 3, then skips the loop back to 0x206.
+        let rom = [0x70, 0x01, 0x30, 0x03, 0x12, 0x00, 0x12, 0x06];
+        let mut chip8 = machine(&rom);
+        assert!(chip8.run_until_pc(0x206, 100).unwrap());
+        assert_eq!(chip8.reg(0), 3);
+
+        let mut chip8 = machine(&rom);
+        assert!(!chip8.run_until_pc(0x206, 4).unwrap());
+        assert_eq!(chip8.reg(0), 2);
+    }
+
+    #[test]
+    fn display_diff_finds_the_one_pixel() {
+        let chip8 = machine(&[]);
+        let mut expected = chip8.display_snapshot();
+        assert!(chip8.display_matches(&expected));
+        expected[64 * 3 + 5] = true;
+        assert_eq!(chip8.display_diff(&expected), vec![(5, 3)]);
+        assert!(!chip8.display_matches(&expected));
+    }
+
+    #[test]
+    fn add_val_leaves_vf_alone_by_default() {
+        let rom = [0x60, 0xFF, 0x70, 0x02];
+        let mut chip8 = machine(&rom);
+        chip8.set_reg(0xF, 0x42);
+        chip8.step().unwrap();
+        chip8.step().unwrap();
+        assert_eq!((chip8.reg(0), chip8.reg(0xF)), (0x01, 0x42));
+
+        let mut chip8 = Chip8Builder::new().quirks(Quirks { add_val_sets_vf : true, .. Quirks::default() }).build();
+        chip8.load_rom_bytes(&rom).unwrap();
+        chip8.step().unwrap();
+        chip8.step().unwrap();
+        assert_eq!((chip8.reg(0), chip8.reg(0xF)), (0x01, 1));
+    }
+
+    #[test]
+    fn scrolling_plane_2_leaves_plane_1_alone() {
+        let mut chip8 = Chip8Builder::new().quirks(Quirks { xochip : true, .. Quirks::default() }).build();
+        chip8.load_rom_bytes(&[0xA2, 0x00, 0xD0, 0x05, 0xF2, 0x01, 0xD0, 0x05, 0x00, 0xC2]).unwrap();
+        for _ in 0..4 {
+            chip8.step().unwrap();
+        }
+        let plane1 = chip8.display_memory.clone();
+        assert_eq!(chip8.plane2, plane1);
+        chip8.step().unwrap();
+        assert_eq!(chip8.display_memory, plane1);
+        let mut scrolled = vec![false; 64 * 2];
+        scrolled.extend_from_slice(&plane1[..64 * 30]);
+        assert_eq!(chip8.plane2, scrolled);
+    }
+
+    #[test]
+    fn fx0a_ignores_a_key_held_before_it_began() {
+        let mut chip8 = machine(&[0xF0, 0x0A, 0x12, 0x02]);
+        chip8.set_key(3, true);
+        for _ in 0..3 {
+            chip8.step().unwrap();
+        }
+        assert!(chip8.waiting_for_key());
+        chip8.set_key(3, false);
+        chip8.step().unwrap();
+        chip8.set_key(3, true);
+        chip8.step().unwrap();
+        assert!(chip8.waiting_for_key());
+        assert_eq!(chip8.pc(), 0x200);
+        chip8.set_key(3, false);
+        chip8.step().unwrap();
+        assert!(!chip8.waiting_for_key());
+        assert_eq!((chip8.pc(), chip8.reg(0)), (0x202, 3));
+    }
+
+    #[test]
+    fn memory_size_follows_the_mode() {
+        let chip8 = Chip8Builder::new().build();
+        assert_eq!((chip8.memory.len(), chip8.mem_mask()), (0x1000, 0x0FFF));
+        let chip8 = Chip8Builder::new().quirks(Quirks { schip : true, .. Quirks::default() }).build();
+        assert_eq!((chip8.memory.len(), chip8.mem_mask()), (0x1000, 0x0FFF));
+        let chip8 = Chip8Builder::new().quirks(Quirks { xochip : true, .. Quirks::default() }).build();
+        assert_eq!((chip8.memory.len(), chip8.mem_mask()), (0x10000, 0xFFFF));
+    }
+
+    #[test]
+    fn odd_length_rom_loads_and_steps() {
+        let mut chip8 = machine(&[0x60, 0x01, 0x61, 0x02, 0x71]);
+        assert_eq!(chip8.drain_warnings(), vec![Warning::OddRom { len : 5 }]);
+        for _ in 0..3 {
+            chip8.step().unwrap();
+        }
+        // The trailing byte ran as 7100.
+        assert_eq!((chip8.reg(0), chip8.reg(1), chip8.pc()), (1, 2, 0x206));
+        assert!(chip8.drain_warnings().is_empty());
+    }
+
+    #[test]
+    fn queued_keys_wait_for_the_next_frame() {
+        let mut chip8 = machine(&[0x60, 0x01].repeat(4));
+        chip8.latch_keys();
+        chip8.step().unwrap();
+        chip8.queue_key(5, true);
+        let keys = *chip8.keys();
+        chip8.step().unwrap();
+        chip8.step().unwrap();
+        assert_eq!(chip8.keys(), &keys);
+        chip8.latch_keys();
+        assert!(chip8.keys()[5]);
+    }
+
+    #[test]
+    fn rom_loads_from_a_reader() {
+        let chip8 = load_rom_reader(Chip8Builder::new().build(), &[0x12, 0x34, 0x56][..]).unwrap();
+        assert_eq!(chip8.pc(), 0x200);
+        assert_eq!(&chip8.memory[0x200..0x204], &[0x12, 0x34, 0x56, 0x00]);
+        assert!(matches!(load_rom_reader(Chip8Builder::new().build(), &[0; 0xE01][..]), Err(Chip8Error::RomTooLarge { .. })));
+    }
+
+    #[test]
+    fn screen_text_of_a_halted_program() {
+        let chip8 = machine(&[0xA2, 0x06, 0xD0, 0x03, 0x12, 0x04, 0x80, 0x40, 0x20]);
+        let watchdog = Watchdog { max_cycles : 100, max_time : None };
+        let (chip8, status) = run_headless(chip8, watchdog).unwrap();
+        assert_eq!(status, RunStatus::Halted);
+        let text = screen_text(&chip8.display_memory);
+        let rows = text.lines().collect::<Vec<&str>>();
+        assert_eq!(rows.len(), 32);
+        assert_eq!(rows[0], format!("#{}", " ".repeat(63)));
+        assert_eq!(rows[1], format!(" #{}", " ".repeat(62)));
+        assert_eq!(rows[2], format!("  #{}", " ".repeat(61)));
+        assert!(rows[3..].iter().all(|row| *row == " ".repeat(64)));
+    }
+
+    #[test]
+    fn test_case_plays_keypad_input() {
+        // Waits for key 5, then draws one pixel at (5, 0).
+        let rom = [0x60, 0x05, 0xE0, 0x9E, 0x12, 0x02, 0xA2, 0x0C, 0xD0, 0x11, 0x12, 0x0A, 0x80];
+        let script = InputScript::new().at(20).press(0x5).at(25).release(0x5).build();
+        let chip8 = run_test_case(&rom, &script, 40, Quirks::default()).unwrap();
+        assert!(chip8.display_memory[5]);
+        assert_eq!(chip8.pc(), 0x20A);
+        let chip8 = run_test_case(&rom, &[], 40, Quirks::default()).unwrap();
+        assert!(!chip8.display_memory[5]);
+    }
+
+    #[test]
+    fn poke_then_peek() {
+        let mut chip8 = machine(&[]);
+        chip8.poke(0x300, 0xAB).unwrap();
+        assert_eq!(chip8.peek(0x300).unwrap(), 0xAB);
+        assert!(matches!(chip8.poke(0x1000, 1), Err(Chip8Error::MemoryOutOfBounds { addr : 0x1000 })));
+        assert!(chip8.peek(0x1000).is_err());
+    }
+
+    #[test]
+    fn registers_read_back_and_mask_the_index() {
+        let mut chip8 = machine(&[]);
+        chip8.set_reg(0xF, 0x7E);
+        assert_eq!(chip8.reg(0xF), 0x7E);
+        chip8.set_reg(0x13, 0x55);
+        assert_eq!(chip8.reg(0x3), 0x55);
+        assert_eq!(chip8.reg(0x23), 0x55);
+        chip8.set_i(0x345);
+        assert_eq!((chip8.i(), chip8.pc()), (0x345, 0x200));
+    }
+
+    #[test]
+    fn report_counts_an_unknown_opcode_and_a_draw() {
+        // The unknown opcode comes last, as it doesn't advance PC.
+        let mut chip8 = strict_machine(&[0x80, 0x06, 0xF0, 0x1E, 0xD0, 0x05, 0xF0, 0xFF]);
+        for _ in 0..4 {
+            chip8.step().unwrap();
+        }
+        let report = chip8.report();
+        assert_eq!(report.cycles, 4);
+        assert_eq!(report.unknown_opcodes.iter().collect::<Vec<_>>(), vec![(&0xF0FF, &1)]);
+        assert_eq!(report.opcode_counts.get("Dxyn"), Some(&1));
+        // A shift of a register by itself reads the same under either quirk.
+        assert!(!report.quirk_sensitive);
+    }
+
+    #[test]
+    fn report_is_quirk_sensitive_once_the_quirk_log_isnt_empty() {
+        let mut chip8 = strict_machine(&[0x61, 0x02, 0x80, 0x16]);
+        chip8.step().unwrap();
+        chip8.step().unwrap();
+        assert!(chip8.report().quirk_sensitive);
+    }
+
+    // The lit columns of row 0 after drawing an 8-pixel wide line at (x, 0).
+    fn draw_line_at(x: u8, quirks: Quirks) -> Vec<usize> {
+        let mut chip8 = Chip8Builder::new().quirks(quirks).build();
+        chip8.load_rom_bytes(&[0xA2, 0x06, 0xD0, 0x11, 0x12, 0x04, 0xFF]).unwrap();
+        chip8.set_reg(0, x);
+        chip8.step().unwrap();
+        chip8.step().unwrap();
+        return (0..64).filter(|&x| chip8.display_memory[x]).collect();
+    }
+
+    #[test]
+    fn origin_wraps_and_body_clips_by_default() {
+        assert_eq!(draw_line_at(60, Quirks::default()), vec![60, 61, 62, 63]);
+        assert_eq!(draw_line_at(124, Quirks::default()), vec![60, 61, 62, 63]);
+    }
+
+    #[test]
+    fn clamped_origin_with_a_clipped_body() {
+        let quirks = Quirks { clamp_origin : true, .. Quirks::default() };
+        assert_eq!(draw_line_at(60, quirks), vec![60, 61, 62, 63]);
+        assert_eq!(draw_line_at(124, quirks), vec![63]);
+    }
+
+    #[test]
+    fn wrapped_origin_with_a_wrapped_body() {
+        let quirks = Quirks { wrap_sprites : true, .. Quirks::default() };
+        assert_eq!(draw_line_at(60, quirks), vec![0, 1, 2, 3, 60, 61, 62, 63]);
+        assert_eq!(draw_line_at(124, quirks), vec![0, 1, 2, 3, 60, 61, 62, 63]);
+    }
+
+    #[test]
+    fn set_display_reads_back() {
+        let mut chip8 = machine(&[]);
+        let pixels = (0..64 * 32).map(|i| i % 3 == 0).collect::<Vec<bool>>();
+        chip8.set_display(&pixels).unwrap();
+        assert_eq!(chip8.frame_buffer(), &pixels[..]);
+        assert!(matches!(chip8.set_display(&[true; 10]),
+            Err(Chip8Error::DisplaySizeMismatch { size : 10, expected : 2048 })));
+    }
+
+    #[test]
+    fn self_test_catches_a_corrupted_font() {
+        let mut chip8 = machine(&[0x00, 0xE0]);
+        assert!(chip8.self_test().is_ok());
+        chip8.poke(FONT_BASE as u16 + 3, 0x00).unwrap();
+        let e = chip8.self_test().unwrap_err();
+        assert!(matches!(e, Chip8Error::SelfTestFailed(_)));
+        assert_eq!(e.to_string(), "The self-test failed: The font in memory is corrupted.");
+    }
+
+    #[test]
+    fn press_then_release_gives_down_then_up() {
+        let mut chip8 = machine(&[]);
+        chip8.queue_key(7, true);
+        chip8.latch_keys();
+        chip8.queue_key(7, false);
+        chip8.latch_keys();
+        assert_eq!(chip8.drain_key_events(), vec![KeyEvent::Down(7), KeyEvent::Up(7)]);
+        assert!(chip8.drain_key_events().is_empty());
+    }
+
+    fn schip_hires_machine(rom: &[u8]) -> Chip8 {
+        let mut chip8 = Chip8Builder::new().quirks(Quirks { schip : true, .. Quirks::default() }).build();
+        let mut program = vec![0x00, 0xFF];
+        program.extend_from_slice(rom);
+        chip8.load_rom_bytes(&program).unwrap();
+        chip8.step().unwrap();
+        return chip8;
+    }
+
+    #[test]
+    fn schip_hires_vf_counts_the_colliding_rows() {
+        let mut chip8 = schip_hires_machine(&[0xA2, 0x0A, 0xD0, 0x13, 0xD0, 0x13, 0x12, 0x08, 0x80, 0x00, 0xFF]);
+        chip8.step().unwrap();
+        chip8.step().unwrap();
+        assert_eq!(chip8.reg(0xF), 0);
+        chip8.step().unwrap();
+        // The empty middle row can't collide.
+        assert_eq!(chip8.reg(0xF), 2);
+    }
+
+    #[test]
+    fn schip_hires_vf_counts_clipped_rows() {
+        let mut chip8 = schip_hires_machine(&[0x61, 0x3E, 0xA2, 0x0A, 0xD0, 0x15, 0x12, 0x08, 0x80, 0x80, 0x80, 0x80, 0x80]);
+        for _ in 0..3 {
+            chip8.step().unwrap();
+        }
+        assert_eq!(chip8.reg(0xF), 3);
+    }
+
+    #[test]
+    fn lores_vf_ignores_clipped_rows() {
+        let mut chip8 = machine(&[0x61, 0x1E, 0xD0, 0x15]);
+        chip8.step().unwrap();
+        chip8.step().unwrap();
+        assert_eq!(chip8.reg(0xF), 0);
+        let mut chip8 = Chip8Builder::new().quirks(Quirks { schip : true, .. Quirks::default() }).build();
+        chip8.load_rom_bytes(&[0x61, 0x1E, 0xD0, 0x15]).unwrap();
+        chip8.step().unwrap();
+        chip8.step().unwrap();
+        assert_eq!(chip8.reg(0xF), 0);
+    }
+
+    #[test]
+    fn losing_focus_pauses_until_it_comes_back() {
+        let mut chip8 = Chip8Builder::new().instructions_per_frame(2).build();
+        chip8.load_rom_bytes(&[0x60, 0x09, 0xF0, 0x15, 0x12, 0x04]).unwrap();
+        chip8.queue_key(4, true);
+        chip8.latch_keys();
+        chip8.step_frame().unwrap();
+        assert!(chip8.keys()[4]);
+        chip8.focus_lost();
+        assert!(chip8.paused());
+        assert!(!chip8.keys()[4]);
+        assert!(chip8.timers_frozen());
+        chip8.step_frame().unwrap();
+        chip8.tick_timers();
+        assert_eq!((chip8.pc(), chip8.delay_timer), (0x204, 8));
+        chip8.focus_gained();
+        assert!(!chip8.paused());
+        assert!(!chip8.timers_frozen());
+        chip8.step_frame().unwrap();
+        assert_eq!(chip8.delay_timer, 7);
+    }
+
+    #[test]
+    fn regaining_focus_keeps_timers_frozen_by_hand() {
+        let mut chip8 = machine(&[]);
+        chip8.set_freeze_timers(true);
+        chip8.focus_lost();
+        chip8.focus_lost();
+        chip8.focus_gained();
+        assert!(chip8.timers_frozen());
+    }
+
+    #[test]
+    fn odd_pc_is_an_error_only_when_enforced() {
+        let rom = [0x12, 0x03, 0x00, 0x00];
+        let mut chip8 = Chip8Builder::new().enforce_alignment(true).build();
+        chip8.load_rom_bytes(&rom).unwrap();
+        chip8.step().unwrap();
+        assert!(matches!(chip8.step(), Err(Chip8Error::MisalignedPc { pc : 0x203 })));
+
+        let mut chip8 = machine(&rom);
+        chip8.step().unwrap();
+        assert!(chip8.step().is_ok());
+    }
+
+    // The variant name in an opcode's Debug output, e.g. "DrawSprite".
+    fn variant(opcode: &MetaOpcode) -> String {
+        let debug = format!("{:?}", opcode);
+        let inner = debug.split_once('(').map_or(&debug[..], |(_, rest)| rest);
+        return inner.split(|c: char| !c.is_alphanumeric()).next().unwrap().to_string();
+    }
+
+    #[test]
+    fn every_supported_opcode_decodes_to_its_own_variant() {
+        let mut variants = std::collections::BTreeSet::new();
+        for pattern in supported_opcodes() {
+            let sample = pattern.chars().map(|c| if c.is_ascii_lowercase() { '1' } else { c }).collect::<String>();
+            let opcode = parse_opcode(u16::from_str_radix(&sample, 16).unwrap());
+            assert!(!matches!(opcode, Unknown(_)), "{} decodes as unknown", pattern);
+            assert!(variants.insert(variant(&opcode)), "{} decodes to an opcode listed twice", pattern);
+        }
+        // Every FlowControlOpcode and RegularOpcode variant, 4 and 41.
+        assert_eq!(variants.len(), 45);
+    }
+
+    #[test]
+    fn fx0a_completes_on_release_by_default() {
+        let mut chip8 = machine(&[0xF0, 0x0A]);
+        chip8.step().unwrap();
+        chip8.set_key(0xB, true);
+        chip8.step().unwrap();
+        assert!(chip8.waiting_for_key());
+        chip8.set_key(0xB, false);
+        chip8.step().unwrap();
+        assert_eq!((chip8.pc(), chip8.reg(0)), (0x202, 0xB));
+    }
+
+    #[test]
+    fn fx0a_completes_on_press_with_the_quirk() {
+        let mut chip8 = Chip8Builder::new().quirks(Quirks { fx0a_on_press : true, .. Quirks::default() }).build();
+        chip8.load_rom_bytes(&[0xF0, 0x0A]).unwrap();
+        chip8.step().unwrap();
+        chip8.set_key(0xB, true);
+        chip8.step().unwrap();
+        assert!(!chip8.waiting_for_key());
+        assert_eq!((chip8.pc(), chip8.reg(0)), (0x202, 0xB));
+    }
+
+    #[test]
+    fn adding_vf_into_a_register_leaves_the_carry_in_vf() {
+        let mut chip8 = machine(&[0x80, 0xF4]);
+        chip8.set_reg(0, 0xFF);
+        chip8.set_reg(0xF, 0x02);
+        chip8.step().unwrap();
+        assert_eq!((chip8.reg(0), chip8.reg(0xF)), (0x01, 1));
+    }
+
+    #[test]
+    fn adding_into_vf_leaves_the_carry_in_vf() {
+        let mut chip8 = machine(&[0x8F, 0x14, 0x8F, 0x14]);
+        chip8.set_reg(0xF, 0xF0);
+        chip8.set_reg(1, 0x20);
+        chip8.step().unwrap();
+        assert_eq!(chip8.reg(0xF), 1);
+        chip8.step().unwrap();
+        assert_eq!(chip8.reg(0xF), 0);
+    }
+
+    #[test]
+    fn draw_log_counts_the_overlapping_pixels() {
+        // The second sprite, 0x3C, overlaps the first, 0xF0, on two pixels.
+        let mut chip8 = Chip8Builder::new().log_draws(true).build();
+        chip8.load_rom_bytes(&[0xA2, 0x0A, 0xD0, 0x01, 0xA2, 0x0B, 0xD0, 0x01, 0x12, 0x08, 0xF0, 0x3C]).unwrap();
+        for _ in 0..4 {
+            chip8.step().unwrap();
+        }
+        let log = chip8.drain_draw_log();
+        assert_eq!(log.len(), 2);
+        assert_eq!((log[0].collisions, log[0].vf), (0, 0));
+        assert_eq!(log[1], DrawEvent { pc : 0x206, x : 0, y : 0, height : 1, collisions : 2, vf : 1 });
+    }
+
+    // The lit pixels after drawing `n` rows of all-set sprite bytes at (0, 0).
+    fn draw_solid(n: u8, builder: Chip8Builder) -> (Chip8, Vec<(usize, usize)>) {
+        let mut rom = vec![0xA2, 0x06, 0xD0, n, 0x12, 0x04];
+        rom.extend_from_slice(&[0xFF; 32]);
+        let mut chip8 = builder.build();
+        chip8.load_rom_bytes(&rom).unwrap();
+        chip8.step().unwrap();
+        chip8.step().unwrap();
+        let lit = (0..64 * 32).filter(|&i| chip8.display_memory[i]).map(|i| (i % 64, i / 64)).collect();
+        return (chip8, lit);
+    }
+
+    #[test]
+    fn tallest_standard_sprite_is_8_by_15() {
+        let (_, lit) = draw_solid(15, Chip8Builder::new());
+        assert_eq!(lit.len(), 8 * 15);
+        assert!(lit.iter().all(|&(x, y)| x < 8 && y < 15));
+    }
+
+    #[test]
+    fn dxy0_is_16_by_16_under_schip() {
+        let (_, lit) = draw_solid(0, Chip8Builder::new().quirks(Quirks { schip : true, .. Quirks::default() }));
+        assert_eq!(lit.len(), 16 * 16);
+        assert!(lit.iter().all(|&(x, y)| x < 16 && y < 16));
+    }
+
+    #[test]
+    fn dxy0_draws_nothing_without_schip_and_is_logged() {
+        let (chip8, lit) = draw_solid(0, Chip8Builder::new().strict_quirks(true));
+        assert!(lit.is_empty());
+        assert!(matches!(chip8.quirk_log(), [QuirkEvent::EmptySprite { pc : 0x202 }]));
+    }
+
+    #[test]
+    fn step_frame_runs_a_frame_and_ticks_once() {
+        let mut chip8 = Chip8Builder::new().instructions_per_frame(5).build();
+        chip8.load_rom_bytes(&[0x60, 0x09, 0xF0, 0x15, 0xF0, 0x18, 0xA0, 0x00, 0xD0, 0x05, 0x12, 0x0A]).unwrap();
+        let frame = chip8.step_frame().unwrap();
+        assert_eq!(chip8.report().cycles, 5);
+        assert_eq!((chip8.delay_timer, chip8.sound_timer), (8, 8));
+        assert_eq!(frame, FrameResult { drew : true, sound : true });
+        let frame = chip8.step_frame().unwrap();
+        assert_eq!(chip8.report().cycles, 10);
+        assert_eq!(chip8.delay_timer, 7);
+        assert!(!frame.drew);
+    }
+
+    // The row of the pixel drawn at (0, 0) once a lores 00C4 has run.
+    fn row_after_lores_scroll(half_lores_scroll: bool) -> usize {
+        let quirks = Quirks { schip : true, half_lores_scroll, .. Quirks::default() };
+        let mut chip8 = Chip8Builder::new().quirks(quirks).build();
+        chip8.load_rom_bytes(&[0xA2, 0x06, 0xD0, 0x01, 0x00, 0xC4, 0x80]).unwrap();
+        for _ in 0..3 {
+            chip8.step().unwrap();
+        }
+        let lit = (0..64 * 32).filter(|&i| chip8.display_memory[i]).collect::<Vec<usize>>();
+        assert_eq!(lit.len(), 1);
+        return lit[0] / 64;
+    }
+
+    #[test]
+    fn lores_scroll_is_halved_only_with_the_quirk() {
+        assert_eq!(row_after_lores_scroll(false), 4);
+        assert_eq!(row_after_lores_scroll(true), 2);
+    }
+
+    #[test]
+    fn stack_lists_two_nested_calls_in_order() {
+        let mut chip8 = machine(&[0x22, 0x04, 0x00, 0x00, 0x22, 0x08, 0x00, 0x00, 0x12, 0x08]);
+        chip8.step().unwrap();
+        chip8.step().unwrap();
+        assert_eq!(chip8.stack(), &[0x200, 0x204]);
+        chip8.set_stack_entry(1, 0x300).unwrap();
+        assert_eq!(chip8.stack(), &[0x200, 0x300]);
+        assert!(matches!(chip8.set_stack_entry(2, 0x300), Err(Chip8Error::NoStackEntry { depth : 2 })));
+    }
+
+    #[test]
+    fn random_programs_and_state_never_panic() {
+        let mut rng = rng::SplitMix64::new(159);
+        let modes = [Quirks::default(), Quirks { schip : true, .. Quirks::default() },
+                     Quirks { xochip : true, wrap_sprites : true, .. Quirks::default() }];
+        let bounds = [MemoryBounds::Wrap, MemoryBounds::Clamp, MemoryBounds::Error];
+        for round in 0..300 {
+            let mut chip8 = Chip8Builder::new().quirks(modes[round % 3]).memory_bounds(bounds[round / 3 % 3])
+                .seed(round as u64).build();
+            let rom = (0..0xE00).map(|_| rng.next_u8()).collect::<Vec<u8>>();
+            chip8.load_rom_bytes(&rom).unwrap();
+            for x in 0..16 {
+                chip8.set_reg(x, rng.next_u8());
+            }
+            chip8.set_i(rng.next_u64() as u16);
+            chip8.set_key(rng.next_u8(), true);
+            for _ in 0..200 {
+                if chip8.step().is_err() {
+                    break;
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn shift_quirk_toggled_mid_run_applies_to_the_next_shift() {
+        let mut chip8 = machine(&[0x61, 0x04, 0x82, 0x16, 0x83, 0x16]);
+        chip8.step().unwrap();
+        chip8.step().unwrap();
+        assert_eq!(chip8.reg(2), 0x02);
+        chip8.set_quirk(QuirkKind::Shift, true);
+        chip8.set_reg(3, 0x40);
+        chip8.step().unwrap();
+        assert_eq!(chip8.reg(3), 0x20);
+        assert!(chip8.quirks().shift);
+    }
+
+    #[test]
+    fn fetching_from_the_last_byte_is_an_error() {
+        let mut chip8 = machine(&[0x1F, 0xFF]);
+        chip8.step().unwrap();
+        assert_eq!(chip8.pc(), 0xFFF);
+        assert!(matches!(chip8.peek_opcode(), Err(Chip8Error::MemoryOutOfBounds { addr : 0x1000 })));
+        assert!(matches!(chip8.step(), Err(Chip8Error::MemoryOutOfBounds { addr : 0x1000 })));
+        assert_eq!(fetch_opcode(&chip8), 0x0000);
+    }
+
+    #[test]
+    fn load_store_leaves_vf_alone_by_default() {
+        let rom = [0xA3, 0x00, 0xF2, 0x55, 0xF2, 0x65];
+        let mut chip8 = machine(&rom);
+        chip8.set_reg(0xF, 0x42);
+        for _ in 0..3 {
+            chip8.step().unwrap();
+        }
+        assert_eq!(chip8.reg(0xF), 0x42);
+
+        let mut chip8 = Chip8Builder::new().quirks(Quirks { load_store_clears_vf : true, .. Quirks::default() }).build();
+        chip8.load_rom_bytes(&rom).unwrap();
+        chip8.set_reg(0xF, 0x42);
+        chip8.step().unwrap();
+        chip8.step().unwrap();
+        assert_eq!(chip8.reg(0xF), 0);
+    }
+
+    #[test]
+    fn render_sprite_shows_the_0_glyph() {
+        let chip8 = machine(&[]);
+        assert_eq!(chip8.render_sprite(FONT_BASE as u16, 5), "####    \n#  #    \n#  #    \n#  #    \n####    \n");
+    }
+
+    #[test]
+    fn vip_timing_costs_draw_and_load() {
+        assert_eq!(Timing::CosmacVip.cost(&parse_opcode(0xD015)), 22734);
+        assert_eq!(Timing::CosmacVip.cost(&parse_opcode(0x6001)), 27);
+        assert_eq!(Timing::Fixed.cost(&parse_opcode(0x6001)), 1);
+    }
+
+    // Serves `body` to a single request on a local port.
+    #[cfg(feature = "url")]
+    fn serve_once(body: &'static [u8]) -> String {
+        use std::io::Write;
+        let listener = std::net::TcpListener::bind("127.0.0.1:0").unwrap();
+        let addr = listener.local_addr().unwrap();
+        std::thread::spawn(move || {
+            let (mut stream, _) = listener.accept().unwrap();
+            let mut request = Vec::new();
+            let mut byte = [0; 1];
+            while !request.ends_with(b"\r\n\r\n") && stream.read(&mut byte).unwrap() == 1 {
+                request.push(byte[0]);
+            }
+            write!(stream, "HTTP/1.1 200 OK\r\nContent-Length: {}\r\nConnection: close\r\n\r\n", body.len()).unwrap();
+            stream.write_all(body).unwrap();
+        });
+        return format!("http://{}/rom.ch8", addr);
+    }
+
+    #[cfg(feature = "url")]
+    #[test]
+    fn loads_a_rom_from_a_url() {
+        let chip8 = load_rom_url(init(), &serve_once(&[0x12, 0x00])).unwrap();
+        assert_eq!(&chip8.memory[0x200..0x202], &[0x12, 0x00]);
+    }
+
+    #[cfg(feature = "url")]
+    #[test]
+    fn network_failure_is_an_error() {
+        let port = std::net::TcpListener::bind("127.0.0.1:0").unwrap().local_addr().unwrap().port();
+        let result = load_rom_url(init(), &format!("http://127.0.0.1:{}/rom.ch8", port));
+        assert!(matches!(result, Err(Chip8Error::Network(_))));
+    }
+
+    #[test]
+    fn clamped_sprite_draws_the_rows_in_memory() {
+        let mut chip8 = Chip8Builder::new().memory_bounds(MemoryBounds::Clamp).build();
+        chip8.load_rom_bytes(&[0xD0, 0x18]).unwrap();
+        chip8.memory[0xFFC..0x1000].copy_from_slice(&[0xFF; 4]);
+        chip8.set_i(0xFFC);
+        chip8.step().unwrap();
+        let lit_rows = (0..8).filter(|y| chip8.display_memory[y * 64]).count();
+        assert_eq!(lit_rows, 4);
+        assert_eq!(chip8.drain_warnings(), vec![Warning::SpriteClamped { addr : 0xFFC, rows : 4, height : 8 }]);
+    }
+
+    #[test]
+    fn same_frame_keys_latch_releases_first_then_ascending() {
+        let mut chip8 = machine(&[]);
+        chip8.queue_key(9, true);
+        chip8.latch_keys();
+        chip8.drain_key_events();
+        chip8.queue_key(5, true);
+        chip8.queue_key(2, true);
+        chip8.queue_key(9, false);
+        chip8.latch_keys();
+        assert_eq!(chip8.drain_key_events(), vec![KeyEvent::Up(9), KeyEvent::Down(2), KeyEvent::Down(5)]);
+    }
+
+    #[test]
+    fn functional_step_matches_the_method() {
+        let rom = [0x60, 0x05, 0xA2, 0x10, 0xD0, 0x05, 0x22, 0x0A, 0x12, 0x08, 0x70, 0x01, 0x00, 0xEE];
+        let mut by_method = machine(&rom);
+        let mut by_value = by_method.clone();
+        for _ in 0..6 {
+            by_method.step().unwrap();
+            by_value = step(by_value).unwrap();
+        }
+        assert_eq!(by_value.save_state(), by_method.save_state());
+        assert_eq!(by_value.display_memory, by_method.display_memory);
+    }
+
+    // Runs a program that stores 0xAB at 0x300 and leaves V0 set.
+    fn ran_store() -> Chip8 {
+        let mut chip8 = machine(&[0x60, 0xAB, 0xA3, 0x00, 0xF0, 0x55]);
+        for _ in 0..3 {
+            chip8.step().unwrap();
+        }
+        return chip8;
+    }
+
+    #[test]
+    fn soft_reset_keeps_memory() {
+        let chip8 = reset(ran_store(), ResetKind::SoftKeepRom);
+        assert_eq!(&chip8.memory[0x200..0x206], &[0x60, 0xAB, 0xA3, 0x00, 0xF0, 0x55]);
+        assert_eq!(chip8.memory[0x300], 0xAB);
+        assert_eq!(&chip8.memory[FONT_BASE..FONT_BASE + 80], &FONT[..]);
+        assert_eq!((chip8.pc(), chip8.reg(0)), (0x200, 0));
+    }
+
+    #[test]
+    fn hard_reset_leaves_only_the_font() {
+        let chip8 = reset(ran_store(), ResetKind::HardClearAll);
+        assert_eq!(&chip8.memory[FONT_BASE..FONT_BASE + 80], &FONT[..]);
+        assert_eq!(&chip8.memory[BIG_FONT_BASE..BIG_FONT_BASE + 160], &BIG_FONT[..]);
+        assert!(chip8.memory[..FONT_BASE].iter().all(|&b| b == 0));
+        assert!(chip8.memory[BIG_FONT_BASE + 160..].iter().all(|&b| b == 0));
+        assert_eq!((chip8.pc(), chip8.reg(0)), (0x200, 0));
+    }
+
+    #[test]
+    fn would_collide_checks_without_drawing() {
+        // An 8-pixel line at (10, 4).
+        let mut chip8 = machine(&[0x60, 0x0A, 0x61, 0x04, 0xA2, 0x0A, 0xD0, 0x11, 0x12, 0x08, 0xFF]);
+        for _ in 0..4 {
+            chip8.step().unwrap();
+        }
+        let before = chip8.display_snapshot();
+        assert!(chip8.would_collide(14, 4, &[0x80]));
+        assert!(!chip8.would_collide(40, 20, &[0xFF, 0xFF]));
+        assert_eq!(chip8.display_snapshot(), before);
+    }
+
+    #[test]
+    fn no_rom_means_no_program_loaded() {
+        assert!(!init().program_loaded());
+        assert!(!machine(&[0x00, 0x00]).program_loaded());
+        assert!(machine(&[0x12, 0x00]).program_loaded());
+    }
+
+    #[test]
+    fn fx29_draws_a_custom_glyph() {
+        let mut small = FONT;
+        small[3 * 5..3 * 5 + 5].copy_from_slice(&[0x81, 0x42, 0x24, 0x42, 0x81]);
+        let mut chip8 = Chip8Builder::new().font(Font::new(&small, None).unwrap()).build();
+        chip8.load_rom_bytes(&[0x60, 0x03, 0x61, 0x00, 0xF0, 0x29, 0xD1, 0x15]).unwrap();
+        for _ in 0..4 {
+            chip8.step().unwrap();
+        }
+        let rows = (0..5)
+            .map(|y| (0..8).filter(|x| chip8.display_memory[y * 64 + x]).fold(0u8, |row, x| row | 0x80 >> x))
+            .collect::<Vec<u8>>();
+        assert_eq!(rows, vec![0x81, 0x42, 0x24, 0x42, 0x81]);
+    }
+
+    #[test]
+    fn font_of_the_wrong_size_is_rejected() {
+        assert!(matches!(Font::new(&[0; 79], None), Err(Chip8Error::FontSize { size : 79, expected : 80 })));
+        assert!(matches!(Font::new(&FONT, Some(&[0; 80])), Err(Chip8Error::FontSize { size : 80, expected : 160 })));
+    }
+
+    // Two jumps back and forth, which halt detection doesn't catch.
+    const ENDLESS: [u8; 4] = [0x12, 0x02, 0x12, 0x00];
+
+    #[test]
+    fn endless_rom_times_out_on_cycles() {
+        let watchdog = Watchdog { max_cycles : 1000, max_time : None };
+        let (chip8, status) = run_headless(machine(&ENDLESS), watchdog).unwrap();
+        assert_eq!(status, RunStatus::TimedOut);
+        assert_eq!(chip8.pc(), 0x200);
+    }
+
+    #[test]
+    fn endless_rom_times_out_on_time() {
+        let watchdog = Watchdog { max_cycles : u64::MAX, max_time : Some(Duration::from_millis(10)) };
+        let (_, status) = run_headless(machine(&ENDLESS), watchdog).unwrap();
+        assert_eq!(status, RunStatus::TimedOut);
+    }
+
+    #[test]
+    fn display_hash_tells_screens_apart() {
+        let rom = [0x60, 0x05, 0xA2, 0x08, 0xD0, 0x05, 0x12, 0x06, 0xF0, 0x90, 0x90, 0x90, 0xF0];
+        let mut a = machine(&rom);
+        let mut b = machine(&rom);
+        for _ in 0..3 {
+            a.step().unwrap();
+            b.step().unwrap();
+        }
+        assert_eq!(a.display_snapshot(), b.display_snapshot());
+        assert_eq!(a.display_hash(), b.display_hash());
+        let mut pixels = b.display_snapshot();
+        pixels[0] = !pixels[0];
+        b.set_display(&pixels).unwrap();
+        assert_ne!(a.display_hash(), b.display_hash());
+    }
+
+    fn bcd_at_ffe(bounds: MemoryBounds) -> (Chip8, Result<(), Chip8Error>) {
+        let mut chip8 = Chip8Builder::new().memory_bounds(bounds).build();
+        chip8.load_rom_bytes(&[0x60, 0x7B, 0xF0, 0x33]).unwrap();
+        chip8.set_i(0xFFE);
+        chip8.step().unwrap();
+        let result = chip8.step();
+        return (chip8, result);
+    }
+
+    #[test]
+    fn bcd_past_memory_end_errors_when_strict() {
+        let (_, result) = bcd_at_ffe(MemoryBounds::Error);
+        // FFE and FFF take two digits, the third would go to I + 2.
+        assert!(matches!(result, Err(Chip8Error::MemoryOutOfBounds { addr : 0x1000 })), "{:?}", result);
+    }
+
+    #[test]
+    fn sprite_past_memory_end_errors_at_the_first_missing_byte() {
+        let mut chip8 = Chip8Builder::new().memory_bounds(MemoryBounds::Error).build();
+        chip8.load_rom_bytes(&[0xD0, 0x05]).unwrap();
+        // FFC to FFF hold four rows, the fifth would be at I + 4.
+        chip8.set_i(0xFFC);
+        let result = chip8.step();
+        assert!(matches!(result, Err(Chip8Error::MemoryOutOfBounds { addr : 0x1000 })), "{:?}", result);
+    }
+
+    #[test]
+    fn bcd_past_memory_end_clamps() {
+        let (mut chip8, result) = bcd_at_ffe(MemoryBounds::Clamp);
+        assert!(result.is_ok());
+        assert_eq!(&chip8.memory[0xFFE..], &[1, 2]);
+        assert_eq!(chip8.memory[0], 0);
+        assert_eq!(chip8.drain_warnings(), vec![Warning::BcdClamped { addr : 0xFFE, digits : 2 }]);
+    }
+
+    #[test]
+    fn bcd_past_memory_end_wraps() {
+        let (chip8, result) = bcd_at_ffe(MemoryBounds::Wrap);
+        assert!(result.is_ok());
+        assert_eq!([chip8.memory[0xFFE], chip8.memory[0xFFF], chip8.memory[0]], [1, 2, 3]);
+    }
+
+    #[test]
+    fn exported_rom_reloads_with_the_poked_bytes() {
+        let mut chip8 = machine(&[0x12, 0x00]);
+        chip8.poke(0x202, 0x60).unwrap();
+        chip8.poke(0x203, 0x07).unwrap();
+        chip8.poke(0x206, 0xFF).unwrap();
+        let path = std::env::temp_dir().join(format!("chip8_export_{}.ch8", std::process::id()));
+        let path = path.to_str().unwrap();
+        chip8.export_rom(path).unwrap();
+        let exported = fs::read(path).unwrap();
+        let reloaded = load_rom(init(), path).unwrap();
+        fs::remove_file(path).unwrap();
+        assert_eq!(exported, vec![0x12, 0x00, 0x60, 0x07, 0x00, 0x00, 0xFF]);
+        assert_eq!(&reloaded.memory[0x200..0x207], &exported[..]);
+    }
+
+    #[derive(Default)]
+    struct CountingPresenter {
+        presents: usize,
+        dirty: Vec<Option<DirtyRect>>,
+    }
+
+    impl render::Presenter for CountingPresenter {
+        fn present(&mut self, _display_memory: &[bool], dirty: Option<DirtyRect>) {
+            self.presents += 1;
+            self.dirty.push(dirty);
+        }
+    }
+
+    #[test]
+    fn every_frame_presents_without_a_draw() {
+        let mut chip8 = machine(&[0x70, 0x01, 0x12, 0x00]);
+        let mut presenter = CountingPresenter::default();
+        for _ in 0..3 {
+            let frame = chip8.step_frame_presenting(&mut presenter).unwrap();
+            assert!(!frame.drew);
+        }
+        assert_eq!(presenter.presents, 3);
+        assert_eq!(presenter.dirty, vec![None; 3]);
+    }
+
+    #[test]
+    fn executed_opcodes_lists_each_variant_run() {
+        let mut chip8 = machine(&[0x60, 0x01, 0x70, 0x02, 0x70, 0x03, 0xD0, 0x05]);
+        for _ in 0..4 {
+            chip8.step().unwrap();
+        }
+        assert_eq!(chip8.executed_opcodes(), vec!["6xkk", "7xkk", "Dxyn"]);
+    }
+
+    fn i_after_add(quirks: Quirks, start: u16) -> (u16, u16) {
+        let mut chip8 = Chip8Builder::new().quirks(quirks).build();
+        chip8.load_rom_bytes(&[0x60, 0x02, 0xF0, 0x1E]).unwrap();
+        chip8.set_i(start);
+        let set = chip8.i();
+        chip8.step().unwrap();
+        chip8.step().unwrap();
+        return (set, chip8.i());
+    }
+
+    #[test]
+    fn i_is_masked_to_the_address_space() {
+        assert_eq!(i_after_add(Quirks::default(), 0x1FFF), (0xFFF, 0x001));
+        assert_eq!(i_after_add(Quirks { xochip : true, .. Quirks::default() }, 0x1FFF), (0x1FFF, 0x2001));
+        assert_eq!(i_after_add(Quirks { xochip : true, .. Quirks::default() }, 0xFFFF), (0xFFFF, 0x0001));
+    }
+
+    #[test]
+    fn input_script_builds_ordered_events() {
+        let script = InputScript::new().at(25).release(0x5).at(10).press(0x5).press(0x13).build();
+        assert_eq!(script, vec![(10, 0x5, true), (10, 0x3, true), (25, 0x5, false)]);
+    }
+
+    #[test]
+    fn frozen_timers_hold_while_the_cpu_runs() {
+        let mut chip8 = machine(&[0x60, 0x09, 0xF0, 0x15, 0x70, 0x01]);
+        chip8.step().unwrap();
+        chip8.step().unwrap();
+        chip8.set_freeze_timers(true);
+        chip8.tick_timers();
+        chip8.step().unwrap();
+        assert_eq!((chip8.delay_timer, chip8.pc()), (9, 0x206));
+        chip8.set_freeze_timers(false);
+        chip8.tick_timers();
+        assert_eq!(chip8.delay_timer, 8);
+    }
+
+    #[test]
+    fn checked_constructors_reject_wide_operands() {
+        assert!(matches!(RegularOpcode::load_val_to_reg(0, 0x1FF), Err(Chip8Error::OperandOutOfRange { value : 0x1FF, max : 0xFF })));
+        assert!(matches!(RegularOpcode::add_val_to_reg(0x10, 1), Err(Chip8Error::OperandOutOfRange { value : 0x10, max : 0xF })));
+        assert!(matches!(RegularOpcode::load_val_to_reg(0xA, 0xFF), Ok(RegularOpcode::LoadValToReg { x : 0xA, value : 0xFF })));
+    }
+
+    fn headless_key_wait(action: KeyWaitAction) -> (Chip8, RunStatus) {
+        let mut chip8 = Chip8Builder::new().key_wait_timeout(KeyWaitTimeout { cycles : 20, action }).build();
+        chip8.load_rom_bytes(&[0xF3, 0x0A, 0x12, 0x02]).unwrap();
+        return run_headless(chip8, Watchdog { max_cycles : 1000, max_time : None }).unwrap();
+    }
+
+    #[test]
+    fn headless_key_wait_times_out() {
+        let (chip8, status) = headless_key_wait(KeyWaitAction::Abort);
+        assert_eq!(status, RunStatus::WaitTimedOut);
+        assert_eq!(chip8.pc(), 0x200);
+    }
+
+    #[test]
+    fn headless_key_wait_takes_the_default_key() {
+        let (chip8, status) = headless_key_wait(KeyWaitAction::PressKey(0xB));
+        assert_eq!(status, RunStatus::Halted);
+        assert_eq!(chip8.reg(3), 0xB);
+    }
+
+    #[test]
+    fn small_draw_dirties_just_the_sprite() {
+        let mut chip8 = machine(&[0x60, 0x0A, 0x61, 0x05, 0xA2, 0x0A, 0xD0, 0x12, 0x00, 0xE0, 0xFF, 0xFF]);
+        for _ in 0..4 {
+            chip8.step().unwrap();
+        }
+        assert_eq!(chip8.take_dirty_rect(), Some(DirtyRect { x : 10, y : 5, width : 8, height : 2 }));
+        assert_eq!(chip8.dirty_rect(), None);
+        chip8.step().unwrap();
+        assert_eq!(chip8.take_dirty_rect(), Some(DirtyRect { x : 0, y : 0, width : 64, height : 32 }));
+    }
+
+    #[test]
+    fn skip_on_vf_reads_the_carry_just_written() {
+        // 8014 carries out of FF + 2, so 3F01 skips the 6201.
+        let mut chip8 = machine(&[0x60, 0xFF, 0x61, 0x02, 0x80, 0x14, 0x3F, 0x01, 0x62, 0x01]);
+        for _ in 0..4 {
+            chip8.step().unwrap();
+        }
+        assert_eq!((chip8.reg(0), chip8.reg(0xF)), (0x01, 1));
+        assert_eq!(chip8.pc(), 0x20A);
+        assert_eq!(chip8.reg(2), 0);
+    }
+
+    #[test]
+    fn bcd_digits() {
+        let table = [(0, [0, 0, 0]), (9, [0, 0, 9]), (99, [0, 9, 9]), (100, [1, 0, 0]), (255, [2, 5, 5])];
+        for (value, digits) in table {
+            assert_eq!(bcd(value), digits, "{}", value);
+        }
+    }
+
+    #[test]
+    fn fx33_stores_the_digits_at_i() {
+        for (value, digits) in [(0, [0, 0, 0]), (5, [0, 0, 5]), (50, [0, 5, 0]), (255, [2, 5, 5])] {
+            let mut chip8 = machine(&[0x60, value, 0xA3, 0x00, 0xF0, 0x33]);
+            for _ in 0..3 {
+                chip8.step().unwrap();
+            }
+            assert_eq!(chip8.peek_at_i(3), digits, "{}", value);
+        }
+    }
+
+    #[test]
+    fn post_mortem_shows_the_run_up_to_a_stack_underflow() {
+        let mut chip8 = Chip8Builder::new().crash_trace(2).build();
+        chip8.load_rom_bytes(&[0x60, 0x01, 0x61, 0x02, 0x00, 0xEE]).unwrap();
+        chip8.step().unwrap();
+        chip8.step().unwrap();
+        assert!(matches!(chip8.step(), Err(Chip8Error::StackUnderflow { pc : 0x204 })));
+        let post_mortem = chip8.post_mortem();
+        let lines = post_mortem.lines().collect::<Vec<&str>>();
+        assert_eq!(lines[0], "The last 2 instructions:");
+        assert!(lines[1].starts_with("PC:0x0202 OP:6102"), "{}", post_mortem);
+        assert!(lines[2].starts_with("PC:0x0204 OP:00EE"), "{}", post_mortem);
+        assert!(!post_mortem.contains("PC:0x0200"));
+        assert_eq!(machine(&[]).post_mortem(), "");
+    }
+
+    #[test]
+    fn step_frame_and_on_draw_each_see_a_draw() {
+        let mut chip8 = Chip8Builder::new().instructions_per_frame(2).build();
+        chip8.load_rom_bytes(&[0xA2, 0x06, 0xD0, 0x01, 0x12, 0x04, 0xFF]).unwrap();
+        assert!(chip8.step_frame().unwrap().drew);
+        let mut calls = 0;
+        chip8.on_draw(|_| calls += 1);
+        assert_eq!(calls, 1);
+        chip8.on_draw(|_| calls += 1);
+        assert!(!chip8.step_frame().unwrap().drew);
+        assert_eq!(calls, 1);
+    }
+
+    #[test]
+    fn fx0a_sees_a_tap_between_two_steps() {
+        let mut chip8 = machine(&[0xF0, 0x0A]);
+        chip8.step().unwrap();
+        chip8.set_key(0x4, true);
+        chip8.set_key(0x4, false);
+        chip8.step().unwrap();
+        assert_eq!((chip8.pc(), chip8.reg(0)), (0x202, 0x4));
+    }
+
+    #[test]
+    fn fx0a_keeps_waiting_across_a_drain() {
+        let mut chip8 = machine(&[0xF0, 0x0A]);
+        chip8.step().unwrap();
+        chip8.set_key(0x4, true);
+        chip8.step().unwrap();
+        chip8.drain_key_events();
+        chip8.set_key(0x4, false);
+        chip8.step().unwrap();
+        assert_eq!((chip8.pc(), chip8.reg(0)), (0x202, 0x4));
+    }
+
+    #[test]
+    fn test_case_stops_once_the_program_halts() {
+        // Two loads, then a jump to itself: a huge budget still ends at once.
+        let chip8 = run_test_case(&[0x60, 0x01, 0x61, 0x02, 0x12, 0x04], &[], usize::MAX, Quirks::default()).unwrap();
+        assert_eq!((chip8.pc(), chip8.report().cycles), (0x204, 2));
+    }
+
+    #[test]
+    fn test_case_returns_the_failing_instruction() {
+        // A return with nothing on the stack.
+        let result = run_test_case(&[0x60, 0x01, 0x00, 0xEE], &[], 10, Quirks::default());
+        assert!(matches!(result, Err(Chip8Error::StackUnderflow { pc : 0x202 })), "{:?}", result.map(|_| ()));
+    }
+}
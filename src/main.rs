@@ -1,276 +1,772 @@
-use std::fs;
-use std::convert::TryInto;
+#![allow(clippy::needless_return)]
 
-use rand::rngs::SmallRng;
-use rand::{ Rng, SeedableRng };
+use std::cell::RefCell;
+use std::fs::File;
+use std::io::{ self, BufRead, BufWriter, Read, Write };
+use std::panic::{ self, AssertUnwindSafe };
+use std::process;
+use std::rc::Rc;
+use std::sync::{ Arc, Mutex };
+use std::sync::mpsc::{ channel, Receiver };
+use std::thread;
+use std::time::Instant;
+#[cfg(feature = "watch")]
+use std::time::Duration;
 
-struct Chip8 {
-    memory: Vec<u8>,
-    program_counter: u16,
+use chip8_emulator::*;
 
-    v: Vec<u8>,
-    reg_i: u16,
+const CYCLES_PER_FRAME: u32 = 20;
+const FRAMES: usize = 60;
+const ROM_PATH: &str = "roms/maze.rom";
+const GOLDEN_CYCLES: usize = 1000;
 
-    stack: Vec<u16>,
+// Flags followed by a value, so the value isn't taken for the rom path.
+const VALUE_FLAGS: &[&str] = &[
+    "--trace", "--trace-filter", "--load-address", "--seed", "--check-golden", "--write-golden", "--export-stats", "--memory-bounds",
+    "--key-wait-timeout", "--key-wait-key", "--crash-trace", "--panels",
+];
 
-    delay_timer: u8,
-    sound_timer: u8,
+// The terminal --panels lays out, in (columns, rows): beside other panels
+// the game still gets its 64 columns.
+const PANELS_WINDOW: (usize, usize) = (86, 32);
 
-    keyboard: Vec<bool>,
-    display_memory: Vec<bool>,
+struct Options {
+    rom: String,
+    monitor: bool,
+    verbose: bool,
+    trace: Option<String>,
+    trace_filter: Option<trace::TraceFilter>,
+    strict_quirks: bool,
+    enforce_alignment: bool,
+    memory_bounds: MemoryBounds,
+    key_wait_timeout: Option<KeyWaitTimeout>,
+    shift_quirk: bool,
+    load_store_quirk: bool,
+    load_store_clears_vf: bool,
+    add_i_quirk: bool,
+    add_val_sets_vf: bool,
+    fx0a_on_press: bool,
+    clamp_origin_quirk: bool,
+    wrap_sprites_quirk: bool,
+    half_lores_scroll: bool,
+    schip: bool,
+    xochip: bool,
+    watch: bool,
+    panel: bool,
+    panels: Option<Vec<panel::PanelKind>>,
+    fps: bool,
+    heatmap: bool,
+    crash_trace: usize,
+    print_screen_on_exit: bool,
+    timing: Timing,
+    dump_state_json: bool,
+    report: bool,
+    export_stats: Option<report::StatsFormat>,
+    load_address: u16,
+    seed: Option<u64>,
+    check_golden: Option<String>,
+    write_golden: Option<String>,
+}
 
-    rng: SmallRng,
+fn parse_args(args: &[String]) -> Options {
+    let has = |flag: &str| args.iter().any(|arg| arg == flag);
+    let value = |flag: &str| args.iter()
+        .position(|arg| arg == flag)
+        .and_then(|i| args.get(i + 1));
+    let rom = args.iter().enumerate().skip(1)
+        .find(|&(i, arg)| (!arg.starts_with("--") || arg == "-") && !VALUE_FLAGS.contains(&args[i - 1].as_str()))
+        .map(|(_, arg)| arg.clone());
+    return Options {
+        rom : rom.unwrap_or_else(|| ROM_PATH.to_string()),
+        monitor : has("--monitor"),
+        verbose : has("--verbose"),
+        trace : value("--trace").cloned(),
+        trace_filter : value("--trace-filter")
+            .map(|s| trace::TraceFilter::from_name(s).expect("Invalid --trace-filter, expected draws, jumps, skips, arithmetic, loads, pc=<start>-<end> or v<x>.")),
+        strict_quirks : has("--strict-quirks"),
+        enforce_alignment : has("--enforce-alignment"),
+        memory_bounds : match value("--memory-bounds").map(String::as_str) {
+            None | Some("wrap") => MemoryBounds::Wrap,
+            Some("clamp") => MemoryBounds::Clamp,
+            Some("error") => MemoryBounds::Error,
+            Some(_) => panic!("Invalid --memory-bounds, expected wrap, clamp or error."),
+        },
+        // A blocked Fx0A gives up after --key-wait-timeout instructions,
+        // or presses --key-wait-key if one is given.
+        key_wait_timeout : value("--key-wait-timeout").map(|s| KeyWaitTimeout {
+            cycles : s.parse().expect("Invalid --key-wait-timeout."),
+            action : match value("--key-wait-key") {
+                Some(key) => KeyWaitAction::PressKey(u8::from_str_radix(key, 16).expect("Invalid --key-wait-key.")),
+                None => KeyWaitAction::Abort,
+            },
+        }),
+        shift_quirk : has("--shift-quirk"),
+        load_store_quirk : has("--load-store-quirk"),
+        load_store_clears_vf : has("--load-store-clears-vf"),
+        add_i_quirk : has("--add-i-quirk"),
+        add_val_sets_vf : has("--add-val-sets-vf"),
+        fx0a_on_press : has("--fx0a-on-press"),
+        clamp_origin_quirk : has("--clamp-origin-quirk"),
+        wrap_sprites_quirk : has("--wrap-sprites-quirk"),
+        half_lores_scroll : has("--half-lores-scroll"),
+        schip : has("--schip"),
+        xochip : has("--xochip"),
+        watch : has("--watch"),
+        panel : has("--panel"),
+        panels : value("--panels").map(|s| {
+            s.split(',').map(|name| panel::PanelKind::from_name(name).expect("Invalid --panels.")).collect()
+        }),
+        fps : has("--fps"),
+        heatmap : has("--heatmap"),
+        crash_trace : value("--crash-trace").map_or(0, |s| s.parse().expect("Invalid --crash-trace.")),
+        print_screen_on_exit : has("--print-screen-on-exit"),
+        timing : if has("--vip-timing") { Timing::CosmacVip } else { Timing::Fixed },
+        dump_state_json : has("--dump-state-json"),
+        report : has("--report"),
+        export_stats : value("--export-stats").map(|s| match s.as_str() {
+            "csv" => report::StatsFormat::Csv,
+            "json" => report::StatsFormat::Json,
+            _ => panic!("Invalid --export-stats, expected csv or json."),
+        }),
+        load_address : value("--load-address")
+            .map(|s| parse_address(s).expect("Invalid --load-address."))
+            .unwrap_or(0x200),
+        seed : value("--seed").map(|s| s.parse().expect("Invalid --seed.")),
+        check_golden : value("--check-golden").cloned(),
+        write_golden : value("--write-golden").cloned(),
+    };
 }
 
-fn init() -> Chip8 {
-    Chip8 {
-        memory : vec![0; 4096],
-        v : vec![0; 16],
-        reg_i : 0,
-        program_counter : 512,
-        stack : Vec::new(),
-        delay_timer : 0,
-        sound_timer : 0,
-        keyboard : vec![false; 16],
-        display_memory : vec![false; 64 * 32],
-        rng : SmallRng::from_entropy(),
-    }
+thread_local! {
+    // Every way out of main goes through `exit` or the end of main, which
+    // both run these hooks.
+    static SHUTDOWN: RefCell<shutdown::Shutdown> = RefCell::new(shutdown::Shutdown::new());
 }
 
-#[derive(Debug)]
-enum MetaOpcode {
-    FlowControl(FlowControlOpcode),
-    Regular(RegularOpcode),
-    Unknown(u16),
+fn on_shutdown(hook: impl FnOnce() + 'static) {
+    SHUTDOWN.with(|shutdown| shutdown.borrow_mut().on_shutdown(hook));
 }
-use MetaOpcode::*;
-
-#[derive(Debug)]
-enum FlowControlOpcode {
-    Jump { addr: u16 },
-    JumpPlusV0 { addr: u16 },
-    Call { addr: u16 },
-    Return,
+
+fn exit(code: i32) -> ! {
+    SHUTDOWN.with(|shutdown| shutdown.borrow_mut().run());
+    process::exit(code);
 }
-use FlowControlOpcode::*;
-
-#[derive(Debug)]
-enum RegularOpcode {
-    SysCall,
-    SkipIfRegValEqual { x: u8, value: u8 },
-    SkipIfRegValNotEqual { x: u8, value: u8 },
-    SkipIfRegRegEqual { x: u8, y: u8 },
-    SkipIfRegRegNotEqual { x: u8, y: u8 },
-    SkipIfKeyPressed { x: u8 },
-    SkipIfKeyNotPressed { x: u8 },
-
-    LoadValToReg { x: u8, value: u8 }, 
-    LoadRegToReg { x: u8, y: u8 }, 
-    LoadDelayTimerToReg { x: u8 },
-    LoadKeyToReg { x: u8 },
-    LoadRegToDelayTimer { x: u8 },
-    LoadRegToSoundTimer { x: u8 },
-    LoadValToI { value: u16 },
-    LoadSpriteLocationToI { x: u8 }, 
-    LoadRegBcdToMem { x: u8 },
-    LoadRegsToMem { n: u8 },
-    LoadMemToRegs { n: u8 },
-    LoadRandomAndValToReg { x: u8, value: u8 },
-
-    SubRegFromReg { x: u8, y: u8 }, 
-    SubnRegFromReg { x: u8, y: u8 }, 
-    AddValToReg { x: u8, value: u8 },
-    AddRegToI { x: u8 },
-    OrRegReg { x: u8, y: u8 },
-    AndRegReg { x: u8, y: u8 },
-    XorRegReg { x: u8, y: u8 },
-    AddRegToReg { x: u8, y: u8 },
-    ShiftRightReg { x: u8 },
-    ShiftLeftReg { x: u8 }, 
-
-    ClearScreen,
-    DrawSprite { x: u8, y: u8, n: u8 },
+
+// The terminal's sound is a bell rung as the beeper starts sounding. The
+// beeper is stopped on shutdown like any backend's would be.
+struct Bell {
+    beeper: Arc<Mutex<audio::Beeper>>,
+    sounding: bool,
 }
-use RegularOpcode::*;
-
-fn parse_opcode(opcode: u16) -> MetaOpcode {
-    let a: u8 = (0x000F & (opcode >> 12)).try_into().unwrap();
-    let b: u8 = (0x000F & (opcode >>  8)).try_into().unwrap();
-    let c: u8 = (0x000F & (opcode >>  4)).try_into().unwrap();
-    let d: u8 = (0x000F & (opcode >>  0)).try_into().unwrap();
-
-    let nnn = 0x0FFF & opcode;
-    let  kk: u8 = (0x00FF & opcode).try_into().unwrap();
-
-    return match (a, b, c, d) {
-        (  0,   0, 0xE,   0) => Regular(ClearScreen),
-        (  0,   0, 0xE, 0xE) => FlowControl(Return),
-        (  0,   _,   _,   _) => Regular(SysCall),
-        (  1,   _,   _,   _) => FlowControl(Jump { addr : nnn }),
-        (  2,   _,   _,   _) => FlowControl(Call { addr : nnn }),
-        (  3,   x,   _,   _) => Regular(SkipIfRegValEqual { x, value : kk }),
-        (  4,   x,   _,   _) => Regular(SkipIfRegValNotEqual { x, value : kk }),
-        (  5,   x,   y,   0) => Regular(SkipIfRegRegEqual { x, y }),
-        (  6,   x,   _,   _) => Regular(LoadValToReg { x, value : kk }),
-        (  7,   x,   _,   _) => Regular(AddValToReg { x, value : kk }),
-        (  8,   x,   y,   0) => Regular(LoadRegToReg { x, y }),
-        (  8,   x,   y,   1) => Regular(OrRegReg { x, y }),
-        (  8,   x,   y,   2) => Regular(AndRegReg { x, y }),
-        (  8,   x,   y,   3) => Regular(XorRegReg { x, y }),
-        (  8,   x,   y,   4) => Regular(AddRegToReg { x, y }),
-        (  8,   x,   y,   5) => Regular(SubRegFromReg { x, y }),
-        (  8,   x,   _,   6) => Regular(ShiftLeftReg { x }),
-        (  8,   x,   y,   7) => Regular(SubnRegFromReg { x, y }),
-        (  8,   x,   _, 0xE) => Regular(ShiftRightReg { x }),
-        (  9,   x,   y,   0) => Regular(SkipIfRegRegNotEqual { x, y }),
-        (0xA,   _,   _,   _) => Regular(LoadValToI { value : nnn }),
-        (0xB,   _,   _,   _) => FlowControl(JumpPlusV0 { addr : nnn }),
-        (0xC,   x,   _,   _) => Regular(LoadRandomAndValToReg { x, value : kk }),
-        (0xD,   x,   y,   n) => Regular(DrawSprite { x, y, n }),
-        (0xE,   x,   9, 0xE) => Regular(SkipIfKeyPressed { x }),
-        (0xE,   x, 0xA,   1) => Regular(SkipIfKeyNotPressed { x }),
-        (0xF,   x,   0,   7) => Regular(LoadDelayTimerToReg { x }),
-        (0xF,   x,   0, 0xA) => Regular(LoadKeyToReg { x }),
-        (0xF,   x,   1,   5) => Regular(LoadRegToDelayTimer { x }),
-        (0xF,   x,   1,   8) => Regular(LoadRegToSoundTimer { x }),
-        (0xF,   x,   1, 0xE) => Regular(AddRegToI { x }),
-        (0xF,   x,   2,   9) => Regular(LoadSpriteLocationToI { x }),
-        (0xF,   x,   3,   3) => Regular(LoadRegBcdToMem { x }),
-        (0xF,   n,   5,   5) => Regular(LoadRegsToMem { n }),
-        (0xF,   n,   6,   5) => Regular(LoadMemToRegs { n }),
-        (  _,   _,   _,   _) => Unknown(opcode)
+
+impl Bell {
+    fn new() -> Bell {
+        let beeper = Arc::new(Mutex::new(audio::Beeper::new(audio::AudioConfig::default(), 44100)));
+        SHUTDOWN.with(|shutdown| audio::stop_on_shutdown(&beeper, &mut shutdown.borrow_mut()));
+        return Bell { beeper, sounding : false };
+    }
+
+    fn frame(&mut self, chip8: &Chip8) {
+        let mut sample = [0.0];
+        if let Ok(mut beeper) = self.beeper.lock() {
+            beeper.fill(chip8, &mut sample);
+        }
+        let sounding = sample[0] != 0.0;
+        if sounding && !self.sounding {
+            print!("\x07");
+        }
+        self.sounding = sounding;
     }
 }
 
-fn load_rom(chip8: Chip8, filename: &str) -> Chip8 {
-    let rom = fs::read(filename).expect("Couldn't load the rom.");
-    let mut memory = chip8.memory.to_vec();
-    // FIXME: Still unsure if this is the correct
-    // way to replace a part of vec.
-    memory.splice(512..(512 + rom.len()), rom);
-    return Chip8 { memory , .. chip8 };
+// `--trace -` writes the trace to stdout in place of the usual output.
+type Trace = Option<BufWriter<Box<dyn Write>>>;
+
+fn open_trace(options: &Options) -> Trace {
+    return options.trace.as_ref().map(|path| {
+        let out: Box<dyn Write> = match path.as_str() {
+            "-" => Box::new(io::stdout()),
+            _ => Box::new(File::create(path).unwrap_or_else(|e| {
+                eprintln!("Couldn't create the trace file: {}", e);
+                exit(1);
+            })),
+        };
+        BufWriter::new(out)
+    });
 }
 
-fn push<T: Clone>(vec: Vec<T>, x: T) -> Vec<T> {
-    let mut v = vec.to_vec();
-    v.push(x);
-    return v;
+fn read_rom(options: &Options) -> Vec<u8> {
+    return match options.rom.as_str() {
+        "-" => {
+            let mut rom = Vec::new();
+            io::stdin().lock().read_to_end(&mut rom).map(|_| rom)
+        },
+        path => std::fs::read(path),
+    }.unwrap_or_else(|e| {
+        eprintln!("{}", Chip8Error::Io(e));
+        exit(1);
+    });
 }
 
-fn pop<T: Clone>(vec: Vec<T>) -> (Vec<T>, T) {
-    let mut v = vec.to_vec();
-    let x = v.pop().expect("Can't pop the empty stack.");
-    return (v, x);
+// A golden trace is the rom's trace over its first GOLDEN_CYCLES
+// instructions with seed 0 and no input, or up to where it jumps to
+// itself if that comes first, e.g. roms/maze.golden. When
+// a change to an instruction is intended, regenerate it with
+// `--write-golden <path>`, otherwise check against it with `--check-golden <path>`.
+fn golden(options: &Options, quirks: Quirks) {
+    let rom = read_rom(options);
+    let mut trace = trace_test_case(&rom, &[], GOLDEN_CYCLES, quirks).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        exit(1);
+    }).join("\n");
+    trace.push('\n');
+    if let Some(path) = &options.write_golden {
+        std::fs::write(path, trace).unwrap_or_else(|e| {
+            eprintln!("Couldn't write the golden trace: {}", e);
+            exit(1);
+        });
+        return;
+    }
+    let path = options.check_golden.as_ref().expect("No golden trace to check.");
+    let expected = std::fs::read_to_string(path).unwrap_or_else(|e| {
+        eprintln!("Couldn't read the golden trace: {}", e);
+        exit(1);
+    });
+    match trace.lines().zip(expected.lines()).position(|(actual, expected)| actual != expected) {
+        Some(i) => {
+            eprintln!("The trace differs from {} at cycle {}:", path, i);
+            eprintln!("  expected {}", expected.lines().nth(i).unwrap_or_default());
+            eprintln!("  got      {}", trace.lines().nth(i).unwrap_or_default());
+            exit(1);
+        },
+        None if trace.lines().count() != expected.lines().count() => {
+            eprintln!("The trace has a different length than {}.", path);
+            exit(1);
+        },
+        None => println!("The trace matches {}.", path),
+    }
 }
 
-fn replace<T: Clone>(vec: &Vec<T>, i: u8, x: T) -> Vec<T> {
-    let mut v = vec.to_vec();
-    v[i as usize] = x;
-    return v;
+fn print_warnings(chip8: &mut Chip8) {
+    for warning in chip8.drain_warnings() {
+        eprintln!("Warning: {}.", warning);
+    }
 }
 
-fn byte_to_bits(b: &u8) -> Vec<bool> {
-    return vec![
-        1u8 == (1u8 & (b >> 7)),
-        1u8 == (1u8 & (b >> 6)),
-        1u8 == (1u8 & (b >> 5)),
-        1u8 == (1u8 & (b >> 4)),
-        1u8 == (1u8 & (b >> 3)),
-        1u8 == (1u8 & (b >> 2)),
-        1u8 == (1u8 & (b >> 1)),
-        1u8 == (1u8 & (b >> 0)),
-    ];
+// With --crash-trace, a failing instruction prints what led up to it
+// before the error ends the run.
+fn step_or_report(chip8: Chip8) -> Result<Chip8, Chip8Error> {
+    let mut chip8 = chip8;
+    let result = chip8.step();
+    print_warnings(&mut chip8);
+    if let Err(e) = result {
+        let post_mortem = chip8.post_mortem();
+        if !post_mortem.is_empty() {
+            eprintln!("{}", post_mortem);
+        }
+        return Err(e);
+    }
+    return Ok(chip8);
 }
 
-fn step(chip8: Chip8) -> Chip8 {
-    let raw_opcode =
-        ((chip8.memory[chip8.program_counter as usize] as u16) << 8)
-        | chip8.memory[(chip8.program_counter + 1) as usize] as u16;
-    let meta_opcode = parse_opcode(raw_opcode);
+fn run_frame(chip8: Chip8, budget: u32, options: &Options, trace: &mut Trace) -> Result<Chip8, Chip8Error> {
+    return run_frame_until(chip8, budget, options, trace, |_| false);
+}
 
-    print!("{:04X} ", raw_opcode);
+// Like run_frame, but the frame ends early once `stop` holds after a step.
+fn run_frame_until(chip8: Chip8, budget: u32, options: &Options, trace: &mut Trace, stop: impl Fn(&Chip8) -> bool) -> Result<Chip8, Chip8Error> {
+    let mut chip8 = chip8;
+    // Out of focus nothing runs, not even the timers.
+    if chip8.paused() {
+        return Ok(chip8);
+    }
+    let mut spent = 0;
+    let trace_to_stdout = options.trace.as_deref() == Some("-");
+    chip8.latch_keys();
+    while spent < budget {
+        // Checked after a step, so a frame can always leave where it stopped.
+        if spent > 0 && stop(&chip8) {
+            break;
+        }
+        let raw_opcode = fetch_opcode(&chip8);
+        spent += options.timing.cost(&parse_opcode(raw_opcode));
+        let traced = options.trace_filter.is_none_or(|filter| filter.matches(&chip8));
+        if let Some(out) = trace.as_mut().filter(|_| traced) {
+            writeln!(out, "{}", chip8.trace_line()).expect("Couldn't write the trace.");
+        }
+        if trace_to_stdout {
+            chip8 = step_or_report(chip8)?;
+            continue;
+        }
+        println!("{}", disassemble(raw_opcode));
+        let before = if options.verbose { Some(chip8.clone()) } else { None };
+        chip8 = step_or_report(chip8)?;
+        match before {
+            Some(before) => {
+                println!("{}", diff(&before, &chip8).join(", "));
+                for event in chip8.drain_draw_log() {
+                    println!("{}", event);
+                }
+            },
+            None => println!("{:X?}", (chip8.reg_i, &chip8.v)),
+        }
+    }
+    chip8.tick_timers();
+    // However many sprites were drawn, the screen is redrawn once a frame.
+    // With --panel the screen is drawn docked beside the panel instead.
+    if !trace_to_stdout && !options.panel {
+        match &options.panels {
+            Some(panels) => println!("{}", panel::compose(panels, &chip8, &debugger::Debugger::new(), PANELS_WINDOW).join("\n")),
+            None => chip8.on_draw(display),
+        }
+    }
+    return Ok(chip8);
+}
 
-    match meta_opcode {
-        FlowControl(ref opcode) => println!("{:X?}", opcode),
-        Regular(ref opcode) => println!("{:X?}", opcode),
-        ref unknown => println!("{:X?}", unknown)
+// The speed keys only change the fixed budget, VIP timing is what it is.
+fn frame_budget(options: &Options, cycles_per_frame: u32) -> u32 {
+    return match options.timing {
+        Timing::Fixed => cycles_per_frame,
+        Timing::CosmacVip => VIP_FRAME_MICROS,
     };
+}
+
+// What the hotkeys have changed so far.
+struct Hotkeys {
+    cycles_per_frame: u32,
+    show_keypad: bool,
+}
 
-    return match meta_opcode {
-        FlowControl(opcode) => match opcode {
-            Jump { addr } =>
-                Chip8 { program_counter : addr, .. chip8 },
-            JumpPlusV0 { addr } =>
-                Chip8 { program_counter : addr + chip8.v[0] as u16, .. chip8 },
-            Call { addr } =>
-                Chip8 { program_counter : addr, stack : push(chip8.stack, chip8.program_counter), .. chip8 },
-            Return => {
-                let (new_stack, pc) = pop(chip8.stack);
-                Chip8 { program_counter : pc, stack : new_stack, .. chip8 }
+impl Hotkeys {
+    fn new() -> Hotkeys {
+        return Hotkeys { cycles_per_frame : CYCLES_PER_FRAME, show_keypad : false };
+    }
+
+    fn apply(&mut self, chip8: &mut Chip8, input: Input) {
+        match input {
+            Input::Focus(false) => chip8.focus_lost(),
+            Input::Focus(true) => chip8.focus_gained(),
+            Input::Key('c') => copy_screen(chip8),
+            Input::Key('k') => self.show_keypad = !self.show_keypad,
+            Input::Key('t') => chip8.set_freeze_timers(!chip8.timers_frozen()),
+            Input::Key(key) => self.cycles_per_frame = render::adjust_speed(self.cycles_per_frame, key),
+        }
+    }
+}
+
+// The hotkeys work whether or not --fps shows the speed overlay.
+fn run_frames(chip8: Chip8, options: &Options, trace: &mut Trace, keys: &Receiver<Input>) -> Result<Chip8, Chip8Error> {
+    let mut chip8 = chip8;
+    let mut fps = render::FpsCounter::new(30);
+    let mut hotkeys = Hotkeys::new();
+    let mut bell = Bell::new();
+    for _ in 0..FRAMES {
+        if shutdown::interrupted() {
+            break;
+        }
+        let start = Instant::now();
+        chip8 = run_frame(chip8, frame_budget(options, hotkeys.cycles_per_frame), options, trace)?;
+        fps.record(start.elapsed());
+        bell.frame(&chip8);
+        for input in keys.try_iter() {
+            hotkeys.apply(&mut chip8, input);
+        }
+        if options.fps {
+            println!("{}", render::speed_overlay(fps.fps(), hotkeys.cycles_per_frame));
+        }
+        if hotkeys.show_keypad {
+            println!("{}", render::keypad_overlay(chip8.keys()).join("\n"));
+        }
+        // 16 bytes a character fits 4K in four lines.
+        if let Some(heat) = chip8.memory_heat() {
+            println!("{}", heat.render(chip8.memory.len(), 16).join("\n"));
+        }
+    }
+    return Ok(chip8);
+}
+
+#[cfg(feature = "panel")]
+fn run_with_panel(chip8: Chip8, options: &Options, trace: &mut Trace, keys: &Receiver<Input>) -> Result<Chip8, Chip8Error> {
+    let mut chip8 = chip8;
+    let mut control = panel::ControlPanel::new(&chip8);
+    let mut hotkeys = Hotkeys::new();
+    let mut bell = Bell::new();
+    for _ in 0..FRAMES {
+        if shutdown::interrupted() {
+            break;
+        }
+        if !control.stopped() {
+            let debugger = control.debugger();
+            let budget = frame_budget(options, hotkeys.cycles_per_frame);
+            chip8 = run_frame_until(chip8, budget, options, trace, |chip8| debugger.is_breakpoint(chip8.program_counter))?;
+            control.check_breakpoint(&chip8);
+            bell.frame(&chip8);
+        }
+        for input in keys.try_iter() {
+            match input {
+                Input::Key(key) if control.key(&chip8, key) => {},
+                input => hotkeys.apply(&mut chip8, input),
             }
         }
-        Regular(opcode) => {
-            let res = match opcode {
-                SysCall =>
-                    chip8,
-                LoadValToReg { x, value } =>
-                    Chip8 { v : replace(&chip8.v, x, value), .. chip8 },
-                LoadValToI { value } =>
-                    Chip8 { reg_i : value, .. chip8 },
-                LoadRandomAndValToReg { x, value } => {
-                    let mut g = chip8.rng.clone();
-                    Chip8 { v : replace(&chip8.v, x, g.gen::<u8>() & value), rng : g, .. chip8 }
-                },
-                AddValToReg { x, value } =>
-                    Chip8 { v : replace(&chip8.v, x, chip8.v[x as usize] + value), .. chip8 },
-                SkipIfRegValEqual { x, value } =>
-                    if chip8.v[x as usize] == value {
-                        Chip8 { program_counter : chip8.program_counter + 2, .. chip8 }
-                    } else {
-                        chip8
-                    },
-                DrawSprite { x, y, n } => {
-                    let lines = chip8.memory.get((chip8.reg_i as usize)..((chip8.reg_i + n as u16) as usize)).expect("Idk").into_iter().map(byte_to_bits).collect::<Vec<Vec<bool>>>();
-                    let mut dm = chip8.display_memory.to_vec();
-                    let start_x = chip8.v[x as usize] as usize;
-                    let start_y = chip8.v[y as usize] as usize;
-                    let mut vf = 0;
-                    for (yy, l) in lines.into_iter().enumerate() {
-                        for (xx, pix) in l.into_iter().enumerate() {
-                            let pos = ((start_x + xx) % 64 + ((start_y + yy) % 32) * 64) as usize;
-                            let xored = dm[pos] ^ pix;
-                            if (dm[pos] == true) && (xored == false) {
-                                vf = 1;
-                            }
-                            dm[pos] = xored;
-                        }
-                    }
-                    display(&dm);
-                    Chip8 { display_memory : dm, v : replace(&chip8.v, 0xF, vf), .. chip8 }
-                },
-                _ =>
-                    chip8
-            };
-            Chip8 { program_counter : res.program_counter + 2, .. res }
+        let lines = control.lines(&chip8, hotkeys.cycles_per_frame);
+        println!("{}", panel::dock(&screen_text(&chip8.display_memory), &lines).join("\n"));
+    }
+    return Ok(chip8);
+}
+
+#[cfg(not(feature = "panel"))]
+fn run_with_panel(_chip8: Chip8, _options: &Options, _trace: &mut Trace, _keys: &Receiver<Input>) -> Result<Chip8, Chip8Error> {
+    eprintln!("--panel needs the emulator to be built with the \"panel\" feature.");
+    exit(1);
+}
+
+#[cfg(feature = "watch")]
+fn run_watching(chip8: Chip8, options: &Options, trace: &mut Trace) -> Result<Chip8, Chip8Error> {
+    let mut watcher = watch::NotifyWatcher::new(&options.rom).unwrap_or_else(|e| {
+        eprintln!("Couldn't watch the rom: {}", e);
+        exit(1);
+    });
+    let mut trigger = watch::ReloadTrigger::new(Duration::from_millis(200));
+    let mut chip8 = chip8;
+    let mut bell = Bell::new();
+    while !shutdown::interrupted() {
+        chip8 = run_frame(chip8, frame_budget(options, CYCLES_PER_FRAME), options, trace)?;
+        bell.frame(&chip8);
+        if trigger.poll(&mut watcher, Instant::now()) {
+            match load_rom(reset(chip8.clone(), ResetKind::HardClearAll), &options.rom) {
+                Ok(reloaded) => chip8 = reloaded,
+                Err(e) => eprintln!("{}", e),
+            }
         }
-        Unknown(_) => chip8
+        thread::sleep(Duration::from_micros(1_000_000 / 60));
     }
+    return Ok(chip8);
+}
+
+#[cfg(not(feature = "watch"))]
+fn run_watching(_chip8: Chip8, _options: &Options, _trace: &mut Trace) -> Result<Chip8, Chip8Error> {
+    eprintln!("--watch needs the emulator to be built with the \"watch\" feature.");
+    exit(1);
+}
+
+#[cfg(feature = "clipboard")]
+fn copy_screen(chip8: &Chip8) {
+    let copied = clipboard::SystemClipboard::new()
+        .and_then(|mut clipboard| clipboard::copy_screen(chip8, &mut clipboard));
+    if let Err(e) = copied {
+        eprintln!("Couldn't copy the screen: {}", e);
+    }
+}
+
+#[cfg(not(feature = "clipboard"))]
+fn copy_screen(_chip8: &Chip8) {
+    eprintln!("Copying the screen needs the emulator to be built with the \"clipboard\" feature.");
 }
 
-fn display(display_memory: &Vec<bool>) {
-    for y in 0..32 {
-        for x in 0..64 {
-            print!("{}", if display_memory[x + 64 * y] {"#"} else {" "});
+#[cfg(feature = "url")]
+fn load_url(chip8: Chip8, url: &str) -> Result<Chip8, Chip8Error> {
+    return load_rom_url(chip8, url);
+}
+
+#[cfg(not(feature = "url"))]
+fn load_url(_chip8: Chip8, _url: &str) -> Result<Chip8, Chip8Error> {
+    eprintln!("Loading a rom from a URL needs the emulator to be built with the \"url\" feature.");
+    exit(1);
+}
+
+// The terminal has no key events, so speed, copy, keypad and timer freeze keys are read from stdin
+// lines on a separate thread and picked up between frames.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Input {
+    Key(char),
+    // The terminal's focus reports, ESC [ I on gaining it and ESC [ O
+    // on losing it.
+    Focus(bool),
+}
+
+fn parse_input(line: &str) -> Vec<Input> {
+    let mut inputs = Vec::new();
+    let mut rest = line;
+    while let Some(key) = rest.chars().next() {
+        if let Some(after) = rest.strip_prefix("\x1b[I") {
+            inputs.push(Input::Focus(true));
+            rest = after;
+        } else if let Some(after) = rest.strip_prefix("\x1b[O") {
+            inputs.push(Input::Focus(false));
+            rest = after;
+        } else {
+            inputs.push(Input::Key(key));
+            rest = &rest[key.len_utf8()..];
         }
-        println!();
     }
+    return inputs;
+}
+
+fn spawn_key_reader() -> Receiver<Input> {
+    // Ask the terminal to report focus changes along with the keys, and
+    // to stop again on the way out.
+    print!("\x1b[?1004h");
+    on_shutdown(|| print!("\x1b[?1004l"));
+    let (tx, rx) = channel();
+    thread::spawn(move || {
+        for line in std::io::stdin().lock().lines() {
+            for input in parse_input(&line.unwrap_or_default()) {
+                if tx.send(input).is_err() {
+                    return;
+                }
+            }
+        }
+    });
+    return rx;
 }
 
 fn main() {
-    let mut chip8 = init();
-    chip8 = load_rom(chip8, &"roms/maze.rom");
-    for _ in 0..1200 {
-        chip8 = step(chip8);
-        println!("{:X?}", (chip8.reg_i, &chip8.v));
+    let args = std::env::args().collect::<Vec<String>>();
+    // `validate <rom>` decodes the rom without running it.
+    if args.get(1).map(String::as_str) == Some("validate") {
+        let options = parse_args(&args[1..]);
+        println!("{}", validate::validate_rom(&read_rom(&options), options.load_address));
+        return;
+    }
+    let options = parse_args(&args);
+    let quirks = Quirks {
+        shift : options.shift_quirk,
+        load_store : options.load_store_quirk,
+        load_store_clears_vf : options.load_store_clears_vf,
+        add_i : options.add_i_quirk,
+        add_val_sets_vf : options.add_val_sets_vf,
+        fx0a_on_press : options.fx0a_on_press,
+        clamp_origin : options.clamp_origin_quirk,
+        wrap_sprites : options.wrap_sprites_quirk,
+        half_lores_scroll : options.half_lores_scroll,
+        schip : options.schip,
+        xochip : options.xochip,
+    };
+    if options.check_golden.is_some() || options.write_golden.is_some() {
+        golden(&options, quirks);
+        return;
+    }
+    // The report can only tell whether the run was quirk-sensitive from
+    // the quirk log.
+    let mut builder = Chip8Builder::new()
+        .load_address(options.load_address)
+        .strict_quirks(options.strict_quirks || options.report)
+        .enforce_alignment(options.enforce_alignment)
+        .memory_bounds(options.memory_bounds)
+        .log_draws(options.verbose)
+        .track_memory(options.heatmap)
+        .crash_trace(options.crash_trace);
+    if let Some(seed) = options.seed {
+        builder = builder.seed(seed);
+    }
+    if let Some(timeout) = options.key_wait_timeout {
+        builder = builder.key_wait_timeout(timeout);
+    }
+    if quirks != Quirks::default() {
+        builder = builder.quirks(quirks);
+    }
+    // `-` reads the rom from stdin.
+    let loaded = match options.rom.as_str() {
+        "-" => load_rom_reader(builder.build(), io::stdin().lock()),
+        url if url.starts_with("http://") || url.starts_with("https://") => load_url(builder.build(), url),
+        path => load_rom(builder.build(), path),
+    };
+    let mut chip8 = loaded.and_then(|chip8| chip8.self_test().map(|_| chip8)).unwrap_or_else(|e| {
+        eprintln!("{}", e);
+        exit(1);
+    });
+    print_warnings(&mut chip8);
+    if options.monitor {
+        monitor::run(chip8, std::io::stdin().lock());
+        return;
+    }
+    if !chip8.program_loaded() {
+        eprintln!("No program is loaded: {} is empty or all zeros.", options.rom);
+        exit(1);
+    }
+    // Flush the trace even when the run panics, that's when it's needed most.
+    let trace = Rc::new(RefCell::new(open_trace(&options)));
+    let flushed = Rc::clone(&trace);
+    on_shutdown(move || {
+        if let Some(Err(e)) = flushed.borrow_mut().as_mut().map(|out| out.flush()) {
+            eprintln!("Couldn't write the trace: {}", e);
+        }
+    });
+    shutdown::catch_interrupt();
+    let result = panic::catch_unwind(AssertUnwindSafe(|| {
+        let trace = &mut trace.borrow_mut();
+        if options.watch {
+            run_watching(chip8, &options, trace)
+        } else if options.panel {
+            run_with_panel(chip8, &options, trace, &spawn_key_reader())
+        } else {
+            run_frames(chip8, &options, trace, &spawn_key_reader())
+        }
+    }));
+    let chip8 = match result {
+        Ok(Ok(chip8)) => chip8,
+        Ok(Err(e)) => {
+            eprintln!("{}", e);
+            exit(1);
+        },
+        Err(panic) => {
+            SHUTDOWN.with(|shutdown| shutdown.borrow_mut().run());
+            panic::resume_unwind(panic);
+        },
+    };
+    // 130 is what a shell reports for a run ended by Ctrl-C.
+    if shutdown::interrupted() {
+        exit(130);
+    }
+    SHUTDOWN.with(|shutdown| shutdown.borrow_mut().run());
+    for event in chip8.quirk_log() {
+        println!("Quirk-sensitive: {}", event);
+    }
+    if options.report {
+        println!("{}", chip8.report());
+    }
+    if let Some(format) = options.export_stats {
+        println!("{}", chip8.report().export_stats(format));
+    }
+    if options.print_screen_on_exit {
+        display(&chip8.display_memory);
+    }
+    if options.dump_state_json {
+        println!("{}", chip8.dump_state_json());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn options(args: &[&str]) -> Options {
+        let args = std::iter::once("chip8_emulator").chain(args.iter().cloned())
+            .map(String::from)
+            .collect::<Vec<String>>();
+        return parse_args(&args);
+    }
+
+    // `--trace -` keeps run_frame quiet, and without an open trace
+    // nothing is written either.
+    fn quiet() -> Options {
+        return options(&["--trace", "-"]);
+    }
+
+    fn machine(rom: &[u8]) -> Chip8 {
+        let mut chip8 = Chip8Builder::new().seed(0).build();
+        chip8.load_rom_bytes(rom).unwrap();
+        return chip8;
+    }
+
+    #[test]
+    fn frame_stops_once_the_budget_is_spent() {
+        // Loads cost 1, so a budget of 20 runs exactly 20 of them.
+        let chip8 = run_frame(machine(&[0x60, 0x01].repeat(40)), 20, &quiet(), &mut None).unwrap();
+        assert_eq!(chip8.report().cycles, 20);
+        // D005 costs 9: 0, 9 and 18 are under the budget, 27 isn't.
+        let chip8 = run_frame(machine(&[0xD0, 0x05].repeat(40)), 20, &quiet(), &mut None).unwrap();
+        assert_eq!(chip8.report().cycles, 3);
+    }
+
+    #[test]
+    fn paused_frame_runs_no_cycles() {
+        let mut chip8 = machine(&[0x60, 0x01].repeat(40));
+        chip8.delay_timer = 5;
+        chip8.focus_lost();
+        let mut chip8 = run_frame(chip8, 20, &quiet(), &mut None).unwrap();
+        assert_eq!((chip8.report().cycles, chip8.delay_timer), (0, 5));
+        chip8.focus_gained();
+        let chip8 = run_frame(chip8, 20, &quiet(), &mut None).unwrap();
+        assert_eq!((chip8.report().cycles, chip8.delay_timer), (20, 4));
+    }
+
+    #[test]
+    fn focus_reports_are_picked_out_of_the_keys() {
+        assert_eq!(parse_input("k\x1b[Ot\x1b[I"),
+                   vec![Input::Key('k'), Input::Focus(false), Input::Key('t'), Input::Focus(true)]);
+    }
+
+    #[test]
+    fn k_toggles_the_keypad_overlay() {
+        let mut chip8 = machine(&[]);
+        let mut hotkeys = Hotkeys::new();
+        hotkeys.apply(&mut chip8, Input::Key('k'));
+        assert!(hotkeys.show_keypad);
+        hotkeys.apply(&mut chip8, Input::Key('k'));
+        assert!(!hotkeys.show_keypad);
+    }
+
+    #[test]
+    fn hotkeys_work_without_fps() {
+        let options = quiet();
+        assert!(!options.fps);
+        let (tx, keys) = channel();
+        tx.send(Input::Key('t')).unwrap();
+        tx.send(Input::Key(']')).unwrap();
+        let chip8 = run_frames(machine(&[0x12, 0x00]), &options, &mut None, &keys).unwrap();
+        assert!(chip8.timers_frozen());
+        // The faster budget, 22 a frame from the second frame on.
+        assert_eq!(chip8.report().cycles, 20 + 22 * (FRAMES as u64 - 1));
+    }
+
+    #[test]
+    fn tracing_n_cycles_writes_n_lines() {
+        let path = std::env::temp_dir().join(format!("chip8_trace_{}.log", process::id()));
+        let options = options(&["--trace", path.to_str().unwrap()]);
+        let mut trace = open_trace(&options);
+        run_frame(machine(&[0x60, 0x01].repeat(40)), 25, &options, &mut trace).unwrap();
+        drop(trace);
+        let written = std::fs::read_to_string(&path).unwrap();
+        std::fs::remove_file(&path).unwrap();
+        assert_eq!(written.lines().count(), 25);
+        assert!(written.starts_with("PC:0x0200 OP:6001 "));
+    }
+
+    #[test]
+    fn maze_matches_its_golden_trace() {
+        let mut trace = trace_test_case(include_bytes!("../roms/maze.rom"), &[], GOLDEN_CYCLES, Quirks::default()).unwrap().join("\n");
+        trace.push('\n');
+        assert!(trace == include_str!("../roms/maze.golden"), "regenerate with --write-golden if the change is intended");
+    }
+
+    #[test]
+    fn frame_ends_early_once_stop_holds() {
+        let rom = [0x60, 0x01].repeat(40);
+        let chip8 = run_frame_until(machine(&rom), 20, &quiet(), &mut None, |chip8| chip8.program_counter == 0x206).unwrap();
+        assert_eq!(chip8.report().cycles, 3);
+    }
+
+    #[cfg(feature = "panel")]
+    #[test]
+    fn panel_run_stops_on_a_breakpoint() {
+        let (tx, keys) = channel();
+        // A breakpoint at the start, so the first frame runs nothing past it.
+        tx.send(Input::Key('b')).unwrap();
+        drop(tx);
+        let chip8 = run_with_panel(machine(&[0x70, 0x01, 0x12, 0x00]), &options(&["--trace", "-", "--panel"]), &mut None, &keys).unwrap();
+        // The first frame ran before the key was read; after it the loop stops at 200.
+        assert_eq!(chip8.program_counter, 0x200);
+        assert_eq!(chip8.report().cycles, 20 + 2);
+    }
+
+    #[test]
+    fn bell_rings_as_the_sound_starts_and_stops_on_shutdown() {
+        let mut chip8 = machine(&[]);
+        let mut bell = Bell::new();
+        chip8.sound_timer = 5;
+        bell.frame(&chip8);
+        assert!(bell.sounding);
+        SHUTDOWN.with(|shutdown| shutdown.borrow_mut().run());
+        bell.frame(&chip8);
+        assert!(!bell.sounding);
+    }
+
+    #[test]
+    fn panels_are_picked_at_startup() {
+        let options = options(&["--panels", "game,disasm", "roms/maze.rom"]);
+        assert_eq!(options.panels, Some(vec![panel::PanelKind::Game, panel::PanelKind::Disassembly]));
+        assert_eq!(options.rom, "roms/maze.rom");
+        assert_eq!(quiet().panels, None);
     }
 }
@@ -0,0 +1,184 @@
+use std::io::BufRead;
+
+use crate::{ Chip8, QuirkKind, step, disassemble, dump_memory, fetch_checked, parse_address, print_registers };
+use crate::debugger::{ Debugger, Watch, WatchCondition, WatchTarget };
+
+// How far `c` runs before giving control back, roughly a minute of
+// emulation at the usual speeds.
+const CONTINUE_CYCLES: u64 = 1_000_000;
+
+#[derive(Debug, PartialEq)]
+pub enum MonitorCommand {
+    Step,
+    Registers,
+    Stack,
+    Memory { addr: u16, len: u16 },
+    Poke { addr: u16, value: u8 },
+    Breakpoint { addr: u16 },
+    ToggleWatch { watch: Watch },
+    Export { path: String },
+    SetQuirk { quirk: QuirkKind, on: bool },
+    Continue,
+    Quit,
+}
+use MonitorCommand::*;
+
+pub fn parse_command(line: &str) -> Result<MonitorCommand, String> {
+    let words = line.split_whitespace().collect::<Vec<&str>>();
+    let number = |i: usize| words.get(i)
+        .and_then(|w| parse_address(w))
+        .ok_or(format!("Expected a number in \"{}\".", line.trim()));
+
+    return match words.first() {
+        Some(&"s") => Ok(Step),
+        Some(&"r") => Ok(Registers),
+        Some(&"k") => Ok(Stack),
+        Some(&"m") => {
+            let len = if words.len() > 2 { number(2)? } else { 16 };
+            Ok(Memory { addr : number(1)?, len })
+        },
+        Some(&"p") => match number(2)? {
+            value if value <= 0xFF => Ok(Poke { addr : number(1)?, value : value as u8 }),
+            value => Err(format!("{:X} doesn't fit in a byte.", value)),
+        },
+        Some(&"b") => Ok(Breakpoint { addr : number(1)? }),
+        Some(&"x") => {
+            let quirk = words.get(1).and_then(|w| QuirkKind::from_name(w))
+                .ok_or(format!("Expected a quirk in \"{}\".", line.trim()))?;
+            match words.get(2) {
+                Some(&"on") => Ok(SetQuirk { quirk, on : true }),
+                Some(&"off") => Ok(SetQuirk { quirk, on : false }),
+                _ => Err(format!("Expected on or off in \"{}\".", line.trim())),
+            }
+        },
+        Some(&"v") => {
+            let target = match words.get(1).map(|w| w.to_lowercase()).as_deref() {
+                Some("i") => WatchTarget::I,
+                Some("dt") => WatchTarget::DelayTimer,
+                Some("st") => WatchTarget::SoundTimer,
+                Some(reg) if reg.len() == 2 && reg.starts_with('v') => match u8::from_str_radix(&reg[1..], 16) {
+                    Ok(x) => WatchTarget::Reg(x),
+                    Err(_) => return Err(format!("Unknown register \"{}\".", reg)),
+                },
+                _ => return Err(format!("Expected v0-vF, i, dt or st in \"{}\".", line.trim())),
+            };
+            let condition = match words.get(2) {
+                Some(&"=") => WatchCondition::Equal(number(3)?),
+                Some(&">") => WatchCondition::Above(number(3)?),
+                Some(&"<") => WatchCondition::Below(number(3)?),
+                _ => return Err(format!("Expected =, > or < in \"{}\".", line.trim())),
+            };
+            Ok(ToggleWatch { watch : Watch { target, condition } })
+        },
+        Some(&"w") => match words.get(1) {
+            Some(path) => Ok(Export { path : path.to_string() }),
+            None => Err(format!("Expected a path in \"{}\".", line.trim())),
+        },
+        Some(&"c") => Ok(Continue),
+        Some(&"q") => Ok(Quit),
+        Some(other) => Err(format!("Unknown command \"{}\".", other)),
+        None => Err("Empty command.".to_string()),
+    };
+}
+
+fn show_next(chip8: &Chip8) {
+    match fetch_checked(chip8) {
+        Ok(raw_opcode) => println!("-> {}", disassemble(raw_opcode)),
+        Err(e) => println!("-> {}", e),
+    }
+}
+
+pub fn run(chip8: Chip8, input: impl BufRead) -> Chip8 {
+    let mut chip8 = chip8;
+    let mut debugger = Debugger::new();
+    show_next(&chip8);
+    for line in input.lines() {
+        let line = line.expect("Couldn't read from stdin.");
+        match parse_command(&line) {
+            // Stepping consumes the machine, so keep a copy to fall
+            // back to when the instruction fails.
+            Ok(Step) => match step(chip8.clone()) {
+                Ok(next) => chip8 = next,
+                Err(e) => println!("{}", e),
+            },
+            Ok(Registers) => print_registers(&chip8),
+            Ok(Stack) => {
+                for (depth, addr) in chip8.stack().iter().enumerate() {
+                    println!("{}: call at {:03X}, returns to {:03X}", depth, addr, addr.wrapping_add(2));
+                }
+            },
+            Ok(Memory { addr, len }) => println!("{}", dump_memory(&chip8, addr, len)),
+            Ok(Poke { addr, value }) => if let Err(e) = chip8.poke(addr, value) {
+                println!("{}", e);
+            },
+            Ok(Breakpoint { addr }) => {
+                let set = debugger.toggle_breakpoint(addr);
+                println!("Breakpoint at {:03X} {}.", addr, if set {"set"} else {"cleared"});
+            },
+            Ok(SetQuirk { quirk, on }) => {
+                chip8.set_quirk(quirk, on);
+                println!("{:?} quirk {}.", quirk, if on {"on"} else {"off"});
+            },
+            Ok(ToggleWatch { watch }) => {
+                let removed = debugger.remove_watch(watch);
+                if !removed {
+                    debugger.add_watch(watch);
+                }
+                println!("Watch on {:?} {:?} {}.", watch.target, watch.condition, if removed {"cleared"} else {"set"});
+            },
+            Ok(Export { path }) => match chip8.export_rom(&path) {
+                Ok(()) => println!("Saved the program to {}.", path),
+                Err(e) => println!("{}", e),
+            },
+            Ok(Continue) => match debugger.continue_run(&mut chip8, CONTINUE_CYCLES) {
+                Ok(reason) => println!("{}", reason),
+                Err(e) => println!("{}", e),
+            },
+            Ok(Quit) => break,
+            Err(message) => println!("{}", message),
+        }
+        show_next(&chip8);
+    }
+    return chip8;
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn single_letter_commands() {
+        assert_eq!(parse_command("s"), Ok(Step));
+        assert_eq!(parse_command("r"), Ok(Registers));
+        assert_eq!(parse_command("k"), Ok(Stack));
+        assert_eq!(parse_command("c"), Ok(Continue));
+        assert_eq!(parse_command("  q  "), Ok(Quit));
+    }
+
+    #[test]
+    fn commands_with_addresses() {
+        assert_eq!(parse_command("m 0x200 16"), Ok(Memory { addr : 0x200, len : 16 }));
+        assert_eq!(parse_command("m 512"), Ok(Memory { addr : 0x200, len : 16 }));
+        assert_eq!(parse_command("b 0x2A2"), Ok(Breakpoint { addr : 0x2A2 }));
+        assert_eq!(parse_command("p 0x300 0xFF"), Ok(Poke { addr : 0x300, value : 0xFF }));
+    }
+
+    #[test]
+    fn bad_commands_are_errors() {
+        assert!(parse_command("").is_err());
+        assert!(parse_command("z").is_err());
+        assert!(parse_command("b").is_err());
+        assert!(parse_command("b nowhere").is_err());
+        assert!(parse_command("p 0x300 0x1FF").is_err());
+        assert!(parse_command("m 0x200 lots").is_err());
+    }
+
+    #[test]
+    fn watch_commands() {
+        let watch = |target, condition| Ok(ToggleWatch { watch : Watch { target, condition } });
+        assert_eq!(parse_command("v v5 = 3"), watch(WatchTarget::Reg(5), WatchCondition::Equal(3)));
+        assert_eq!(parse_command("v I > 0x800"), watch(WatchTarget::I, WatchCondition::Above(0x800)));
+        assert!(parse_command("v vz = 3").is_err());
+        assert!(parse_command("v dt ! 3").is_err());
+    }
+}
@@ -0,0 +1,307 @@
+use crate::{ Chip8, disassemble, render, screen_text };
+#[cfg(feature = "panel")]
+use crate::dump_memory;
+use crate::debugger::Debugger;
+
+// What a debugger control panel shows, kept apart from any GUI toolkit
+// so a frontend only has to lay these rows out as widgets.
+
+// (label, value) for every register and timer.
+pub fn register_rows(chip8: &Chip8) -> Vec<(String, String)> {
+    let mut rows = vec![
+        ("PC".to_string(), format!("{:03X}", chip8.program_counter)),
+        ("I".to_string(), format!("{:03X}", chip8.reg_i)),
+    ];
+    for (i, v) in chip8.v.iter().enumerate() {
+        rows.push((format!("V{:X}", i), format!("{:02X}", v)));
+    }
+    rows.push(("DT".to_string(), format!("{:02X}", chip8.delay_timer)));
+    rows.push(("ST".to_string(), format!("{:02X}", chip8.sound_timer)));
+    return rows;
+}
+
+pub struct DisassemblyRow {
+    pub addr: u16,
+    pub text: String,
+    pub current: bool,
+    pub breakpoint: bool,
+}
+
+// The PC's instruction with `before` instructions above it and `after`
+// below, assuming instructions are two bytes apart.
+pub fn disassembly_around(chip8: &Chip8, debugger: &Debugger, before: u16, after: u16) -> Vec<DisassemblyRow> {
+    let pc = chip8.program_counter;
+    let start = pc.saturating_sub(before * 2);
+    return (0..=(before + after))
+        .map(|i| start.wrapping_add(i * 2))
+        .filter_map(|addr| {
+            let high = *chip8.memory.get(addr as usize)?;
+            let low = *chip8.memory.get(addr as usize + 1)?;
+            Some(DisassemblyRow {
+                addr,
+                text : disassemble((high as u16) << 8 | low as u16),
+                current : addr == pc,
+                breakpoint : debugger.is_breakpoint(addr),
+            })
+        })
+        .collect();
+}
+
+// The panes a window can be composed of, chosen at startup with
+// `--panels`, e.g. `--panels game,disasm`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum PanelKind {
+    Game,
+    Disassembly,
+    Registers,
+    Keypad,
+}
+
+impl PanelKind {
+    pub fn from_name(name: &str) -> Option<PanelKind> {
+        return match name {
+            "game" => Some(PanelKind::Game),
+            "disasm" => Some(PanelKind::Disassembly),
+            "registers" => Some(PanelKind::Registers),
+            "keypad" => Some(PanelKind::Keypad),
+            _ => None,
+        };
+    }
+}
+
+// The (x, y, width, height) of every panel in `panels`, in the same
+// order. The game takes the left three quarters of the window and the
+// other panels share the column to its right, top to bottom, evenly;
+// without a game they share the whole window the same way. The game's
+// rectangle is meant for `fit_display`.
+pub fn layout_panels(panels: &[PanelKind], window: (usize, usize)) -> Vec<(usize, usize, usize, usize)> {
+    let has_game = panels.contains(&PanelKind::Game);
+    let side = panels.iter().filter(|&&p| p != PanelKind::Game).count();
+    let game_width = match (has_game, side) {
+        (false, _) => 0,
+        (true, 0) => window.0,
+        (true, _) => window.0 * 3 / 4,
+    };
+    let column_width = window.0 - game_width;
+    let mut row = 0;
+    return panels.iter()
+        .map(|&panel| {
+            if panel == PanelKind::Game {
+                return (0, 0, game_width, window.1);
+            }
+            let top = window.1 * row / side;
+            row += 1;
+            (game_width, top, column_width, window.1 * row / side - top)
+        })
+        .collect();
+}
+
+// `panels` drawn into a terminal of `window` (columns, rows) by
+// layout_panels, each clipped to its rectangle.
+pub fn compose(panels: &[PanelKind], chip8: &Chip8, debugger: &Debugger, window: (usize, usize)) -> Vec<String> {
+    let mut grid = vec![vec![' '; window.0]; window.1];
+    for (&panel, (x, y, width, height)) in panels.iter().zip(layout_panels(panels, window)) {
+        for (row, line) in panel_lines(panel, chip8, debugger).iter().take(height).enumerate() {
+            for (column, c) in line.chars().take(width).enumerate() {
+                grid[y + row][x + column] = c;
+            }
+        }
+    }
+    return grid.into_iter().map(|row| row.into_iter().collect::<String>().trim_end().to_string()).collect();
+}
+
+fn panel_lines(panel: PanelKind, chip8: &Chip8, debugger: &Debugger) -> Vec<String> {
+    return match panel {
+        PanelKind::Game => screen_text(&chip8.display_memory).lines().map(String::from).collect(),
+        PanelKind::Disassembly => disassembly_around(chip8, debugger, 4, 4).iter().map(disassembly_line).collect(),
+        PanelKind::Registers => register_rows(chip8).chunks(2)
+            .map(|pair| pair.iter().map(|(label, value)| format!("{} {}", label, value)).collect::<Vec<String>>().join("  "))
+            .collect(),
+        PanelKind::Keypad => render::keypad_overlay(chip8.keys()),
+    };
+}
+
+// `>` marks the PC and `*` a breakpoint.
+fn disassembly_line(row: &DisassemblyRow) -> String {
+    return format!("{}{} {:03X} {}", if row.current {'>'} else {' '}, if row.breakpoint {'*'} else {' '}, row.addr, row.text);
+}
+
+// The terminal control panel docked beside the game with `--panel`:
+// registers, the disassembly around PC, breakpoints, a memory viewer
+// and the speed. `b` toggles a breakpoint at PC, `g` continues from
+// one, `<` and `>` page the memory viewer; `[` and `]` change the
+// speed as they always do.
+#[cfg(feature = "panel")]
+pub struct ControlPanel {
+    debugger: Debugger,
+    memory_addr: u16,
+    stopped: bool,
+}
+
+#[cfg(feature = "panel")]
+const MEMORY_PAGE: u16 = 0x40;
+
+#[cfg(feature = "panel")]
+impl ControlPanel {
+    pub fn new(chip8: &Chip8) -> ControlPanel {
+        return ControlPanel { debugger : Debugger::new(), memory_addr : chip8.load_address, stopped : false };
+    }
+
+    pub fn debugger(&self) -> &Debugger {
+        return &self.debugger;
+    }
+
+    // While stopped on a breakpoint the frame loop runs nothing.
+    pub fn stopped(&self) -> bool {
+        return self.stopped;
+    }
+
+    pub fn check_breakpoint(&mut self, chip8: &Chip8) {
+        self.stopped |= self.debugger.is_breakpoint(chip8.program_counter);
+    }
+
+    // Whether the panel took the key.
+    pub fn key(&mut self, chip8: &Chip8, key: char) -> bool {
+        let last_page = (chip8.memory.len() as u16).wrapping_sub(MEMORY_PAGE);
+        match key {
+            'b' => { self.debugger.toggle_breakpoint(chip8.program_counter); },
+            'g' => self.stopped = false,
+            '<' => self.memory_addr = self.memory_addr.saturating_sub(MEMORY_PAGE),
+            '>' => self.memory_addr = (self.memory_addr + MEMORY_PAGE).min(last_page),
+            _ => return false,
+        }
+        return true;
+    }
+
+    pub fn lines(&self, chip8: &Chip8, cycles_per_frame: u32) -> Vec<String> {
+        let registers = register_rows(chip8).into_iter()
+            .map(|(label, value)| format!("{} {}", label, value))
+            .collect::<Vec<String>>();
+        let mut lines = vec![registers[..2].join("  ") + "  " + &registers[18..].join("  ")];
+        lines.extend(registers[2..18].chunks(8).map(|chunk| chunk.join(" ")));
+        lines.push(String::new());
+        lines.extend(disassembly_around(chip8, &self.debugger, 4, 4).iter().map(disassembly_line));
+        let breakpoints = self.debugger.breakpoints().iter().map(|addr| format!("{:03X}", addr)).collect::<Vec<String>>();
+        lines.push(format!("Breakpoints: {}", if breakpoints.is_empty() { "none".to_string() } else { breakpoints.join(", ") }));
+        lines.push(String::new());
+        lines.extend(dump_memory(chip8, self.memory_addr, MEMORY_PAGE).lines().map(String::from));
+        lines.push(format!("Speed: {} cycles/frame", cycles_per_frame));
+        if self.stopped {
+            lines.push(format!("Stopped at {:03X}, g continues.", chip8.program_counter));
+        }
+        return lines;
+    }
+}
+
+// `panel` to the right of `game`, line for line.
+#[cfg(feature = "panel")]
+pub fn dock(game: &str, panel: &[String]) -> Vec<String> {
+    let game = game.lines().collect::<Vec<&str>>();
+    let width = game.iter().map(|line| line.chars().count()).max().unwrap_or(0);
+    return (0..game.len().max(panel.len()))
+        .map(|i| {
+            let left = game.get(i).copied().unwrap_or("");
+            let right = panel.get(i).map_or("", String::as_str);
+            format!("{:width$} | {}", left, right, width = width).trim_end().to_string()
+        })
+        .collect();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Chip8Builder;
+
+    #[test]
+    fn register_rows_follow_the_machine() {
+        let mut chip8 = Chip8Builder::new().build();
+        chip8.load_rom_bytes(&[0x6A, 0x42]).unwrap();
+        chip8.step().unwrap();
+        chip8.set_i(0x123);
+        chip8.sound_timer = 5;
+        let rows = register_rows(&chip8);
+        assert_eq!(rows.len(), 20);
+        assert_eq!(rows[0], ("PC".to_string(), "202".to_string()));
+        assert_eq!(rows[1], ("I".to_string(), "123".to_string()));
+        assert_eq!(rows[2 + 0xA], ("VA".to_string(), "42".to_string()));
+        assert_eq!(rows[19], ("ST".to_string(), "05".to_string()));
+    }
+
+    #[test]
+    fn disassembly_marks_the_pc_and_breakpoints() {
+        let mut chip8 = Chip8Builder::new().build();
+        chip8.load_rom_bytes(&[0x60, 0x01, 0x61, 0x02, 0x12, 0x04]).unwrap();
+        chip8.step().unwrap();
+        let mut debugger = Debugger::new();
+        debugger.toggle_breakpoint(0x204);
+        let rows = disassembly_around(&chip8, &debugger, 1, 1);
+        assert_eq!(rows.iter().map(|row| row.addr).collect::<Vec<u16>>(), vec![0x200, 0x202, 0x204]);
+        assert_eq!(rows.iter().map(|row| row.current).collect::<Vec<bool>>(), vec![false, true, false]);
+        assert_eq!(rows.iter().map(|row| row.breakpoint).collect::<Vec<bool>>(), vec![false, false, true]);
+        assert_eq!(rows[0].text, disassemble(0x6001));
+    }
+
+    #[cfg(feature = "panel")]
+    #[test]
+    fn panel_stops_on_a_breakpoint_until_g() {
+        let mut chip8 = Chip8Builder::new().build();
+        chip8.load_rom_bytes(&[0x60, 0x01, 0x61, 0x02, 0x12, 0x04]).unwrap();
+        let mut panel = ControlPanel::new(&chip8);
+        chip8.step().unwrap();
+        assert!(panel.key(&chip8, 'b'));
+        panel.check_breakpoint(&chip8);
+        assert!(panel.stopped());
+        let lines = panel.lines(&chip8, 20);
+        assert!(lines.contains(&format!(">* 202 {}", disassemble(0x6102))), "{:?}", lines);
+        assert!(lines.contains(&"Breakpoints: 202".to_string()));
+        assert!(lines.contains(&"Stopped at 202, g continues.".to_string()));
+        assert!(panel.key(&chip8, 'g'));
+        assert!(!panel.stopped());
+        assert!(!panel.key(&chip8, 'k'));
+    }
+
+    #[cfg(feature = "panel")]
+    #[test]
+    fn panel_pages_the_memory_viewer() {
+        let chip8 = Chip8Builder::new().build();
+        let mut panel = ControlPanel::new(&chip8);
+        panel.key(&chip8, '>');
+        assert!(panel.lines(&chip8, 20).iter().any(|line| line.starts_with("240: ")));
+        for _ in 0..100 {
+            panel.key(&chip8, '>');
+        }
+        assert!(panel.lines(&chip8, 20).iter().any(|line| line.starts_with("FC0: ")));
+        assert!(panel.lines(&chip8, 20).contains(&"Speed: 20 cycles/frame".to_string()));
+    }
+
+    #[cfg(feature = "panel")]
+    #[test]
+    fn dock_puts_the_panel_beside_the_game() {
+        let docked = dock("##\n #\n", &["PC 200".to_string()]);
+        assert_eq!(docked, vec!["## | PC 200".to_string(), " # |".to_string()]);
+    }
+
+    #[test]
+    fn game_takes_three_quarters_beside_stacked_panels() {
+        let panels = [PanelKind::Game, PanelKind::Disassembly, PanelKind::Registers];
+        assert_eq!(layout_panels(&panels, (800, 600)), vec![(0, 0, 600, 600), (600, 0, 200, 300), (600, 300, 200, 300)]);
+        assert_eq!(layout_panels(&[PanelKind::Game], (640, 320)), vec![(0, 0, 640, 320)]);
+        assert_eq!(layout_panels(&[PanelKind::Keypad], (100, 90)), vec![(0, 0, 100, 90)]);
+    }
+
+    #[test]
+    fn compose_draws_each_panel_in_its_rectangle() {
+        let mut chip8 = Chip8Builder::new().build();
+        chip8.load_rom_bytes(&[0x60, 0x01, 0x12, 0x02]).unwrap();
+        chip8.display_memory[0] = true;
+        chip8.step().unwrap();
+        let composed = compose(&[PanelKind::Game, PanelKind::Registers], &chip8, &Debugger::new(), (40, 4));
+        assert_eq!(composed.len(), 4);
+        // The game gets 30 columns, cut from 64, and the registers the last 10.
+        assert_eq!(composed[0], format!("#{}PC 202  I", " ".repeat(29)));
+        assert_eq!(composed[1], format!("{}V0 01  V1", " ".repeat(30)));
+        let composed = compose(&[PanelKind::Disassembly], &chip8, &Debugger::new(), (30, 9));
+        // Four instructions above the PC, then the PC's own.
+        assert!(composed[4].starts_with(">  202 "), "{:?}", composed);
+    }
+}
@@ -0,0 +1,64 @@
+use sha1_smol::Sha1;
+
+use crate::Quirks;
+
+// SHA-1 of known ROMs and the quirks they need.
+const KNOWN_ROMS: &[(&str, &str, Quirks)] = &[
+    ("8b70080adbac44513ec60005734a816372b845ec", "Maze (David Winter)",
+        Quirks { shift : false, load_store : false, load_store_clears_vf : false, add_i : false,
+            add_val_sets_vf : false, fx0a_on_press : false, clamp_origin : false, wrap_sprites : false,
+            half_lores_scroll : false, schip : false, xochip : false }),
+];
+
+pub fn rom_hash(rom: &[u8]) -> String {
+    return Sha1::from(rom).digest().to_string();
+}
+
+pub fn auto_quirks(rom: &[u8]) -> Option<Quirks> {
+    let hash = rom_hash(rom);
+    return known_roms()
+        .find(|(known, _, _)| *known == hash)
+        .map(|(_, _, quirks)| *quirks);
+}
+
+#[cfg(not(test))]
+fn known_roms() -> impl Iterator<Item = &'static (&'static str, &'static str, Quirks)> {
+    return KNOWN_ROMS.iter();
+}
+
+// Tests also know the repo's own fixtures.
+#[cfg(test)]
+fn known_roms() -> impl Iterator<Item = &'static (&'static str, &'static str, Quirks)> {
+    return KNOWN_ROMS.iter().chain(tests::FIXTURE_ROMS);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    pub(super) const FIXTURE_ROMS: &[(&str, &str, Quirks)] = &[
+        // Draws the 8016 result, 1 with the shift quirk and 4 without.
+        ("e122a58b68b1f691b70847dbb37592875d10fb98", "Shift quirk test (roms/shift.rom)",
+            Quirks { shift : true, load_store : false, load_store_clears_vf : false, add_i : false,
+                add_val_sets_vf : false, fx0a_on_press : false, clamp_origin : false, wrap_sprites : false,
+                half_lores_scroll : false, schip : false, xochip : false }),
+    ];
+
+    #[test]
+    fn known_rom_gets_its_profile() {
+        let quirks = auto_quirks(include_bytes!("../roms/shift.rom")).unwrap();
+        assert_eq!(quirks, Quirks { shift : true, .. Quirks::default() });
+        assert_eq!(auto_quirks(include_bytes!("../roms/maze.rom")), Some(Quirks::default()));
+    }
+
+    #[test]
+    fn unknown_rom_has_no_profile() {
+        assert_eq!(auto_quirks(&[0x12, 0x00]), None);
+    }
+
+    #[test]
+    fn shipped_table_has_no_fixtures() {
+        let fixture = rom_hash(include_bytes!("../roms/shift.rom"));
+        assert!(KNOWN_ROMS.iter().all(|(hash, _, _)| *hash != fixture));
+    }
+}
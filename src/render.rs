@@ -0,0 +1,256 @@
+use std::collections::VecDeque;
+use std::time::Duration;
+
+use crate::DirtyRect;
+
+// Phosphor persistence: pixels that turn off fade out over a few frames
+// instead of vanishing, which hides the flicker of erase-and-redraw
+// sprite movement. Only the rendered copy is affected, never the display.
+pub struct Phosphor {
+    fade_rate: f32,
+    brightness: Vec<f32>,
+}
+
+impl Phosphor {
+    // `fade_rate` is the brightness lost per frame, 1.0 disables the effect.
+    pub fn new(fade_rate: f32) -> Phosphor {
+        return Phosphor { fade_rate, brightness : Vec::new() };
+    }
+
+    pub fn update(&mut self, display_memory: &[bool]) -> &[f32] {
+        self.brightness.resize(display_memory.len(), 0.0);
+        for (b, &on) in self.brightness.iter_mut().zip(display_memory) {
+            *b = if on { 1.0 } else { (*b - self.fade_rate).max(0.0) };
+        }
+        return &self.brightness;
+    }
+}
+
+// Where frames go. A backend is handed the display once every frame,
+// drawn to or not, so effects like the phosphor fade keep moving while
+// the program is busy computing. `dirty` is what changed since the
+// last present, None if nothing did.
+pub trait Presenter {
+    fn present(&mut self, display_memory: &[bool], dirty: Option<DirtyRect>);
+}
+
+impl Presenter for Phosphor {
+    fn present(&mut self, display_memory: &[bool], _dirty: Option<DirtyRect>) {
+        self.update(display_memory);
+    }
+}
+
+pub fn brightness_to_char(brightness: f32) -> char {
+    return match brightness {
+        b if b >= 1.0 => '#',
+        b if b > 0.0 => '+',
+        _ => ' ',
+    };
+}
+
+// How 64x32 content is laid out on the 128x64 grid used once SCHIP
+// hires exists, so switching modes mid-game doesn't change the picture size.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum LowresLayout {
+    Scale,
+    Center,
+}
+
+// The (x, y, width, height) rectangle of the 128x64 grid covered by
+// the display pixel at (x, y).
+pub fn pixel_rect(layout: LowresLayout, hires: bool, x: usize, y: usize) -> (usize, usize, usize, usize) {
+    if hires {
+        return (x, y, 1, 1);
+    }
+    return match layout {
+        LowresLayout::Scale => (x * 2, y * 2, 2, 2),
+        LowresLayout::Center => (x + 32, y + 16, 1, 1),
+    };
+}
+
+// Where the display goes inside a resized window: the largest integer
+// scale that fits, centered, with the margins left for the background.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Viewport {
+    pub scale: usize,
+    pub x: usize,
+    pub y: usize,
+}
+
+// A window smaller than the display still gets a scale of 1.
+pub fn fit_display(window: (usize, usize), display: (usize, usize)) -> Viewport {
+    let scale = (window.0 / display.0).min(window.1 / display.1).max(1);
+    return Viewport {
+        scale,
+        x : window.0.saturating_sub(display.0 * scale) / 2,
+        y : window.1.saturating_sub(display.1 * scale) / 2,
+    };
+}
+
+// Rolling average over the last `window` frame durations.
+pub struct FpsCounter {
+    window: usize,
+    frames: VecDeque<Duration>,
+}
+
+impl FpsCounter {
+    pub fn new(window: usize) -> FpsCounter {
+        return FpsCounter { window, frames : VecDeque::with_capacity(window) };
+    }
+
+    pub fn record(&mut self, frame: Duration) {
+        if self.frames.len() == self.window {
+            self.frames.pop_front();
+        }
+        self.frames.push_back(frame);
+    }
+
+    pub fn fps(&self) -> f32 {
+        let total = self.frames.iter().sum::<Duration>().as_secs_f32();
+        return if total > 0.0 { self.frames.len() as f32 / total } else { 0.0 };
+    }
+}
+
+pub fn speed_overlay(fps: f32, cycles_per_frame: u32) -> String {
+    return format!("FPS: {:.1} Cycles/frame: {}", fps, cycles_per_frame);
+}
+
+// `]` speeds the emulation up and `[` slows it down, by roughly 10%.
+pub fn adjust_speed(cycles_per_frame: u32, key: char) -> u32 {
+    let delta = (cycles_per_frame / 10).max(1);
+    return match key {
+        ']' => cycles_per_frame + delta,
+        '[' => cycles_per_frame.saturating_sub(delta).max(1),
+        _ => cycles_per_frame,
+    };
+}
+
+// ARGB colors for XO-CHIP's two planes, indexed by which planes a pixel
+// is set in: background, plane 1 only, plane 2 only, both.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Palette {
+    pub colors: [u32; 4],
+}
+
+// Octo's default colors, which most XO-CHIP games are drawn for.
+impl Default for Palette {
+    fn default() -> Palette {
+        return Palette { colors : [0xFF996600, 0xFFFFCC00, 0xFFFF6600, 0xFF662200] };
+    }
+}
+
+pub fn composite(plane1: &[bool], plane2: &[bool], palette: &Palette) -> Vec<u32> {
+    return plane1.iter().enumerate()
+        .map(|(i, &p1)| {
+            let p2 = plane2.get(i).cloned().unwrap_or(false);
+            palette.colors[p1 as usize | (p2 as usize) << 1]
+        })
+        .collect();
+}
+
+// The COSMAC VIP hex keypad as laid out on the device.
+pub const KEYPAD: [[u8; 4]; 4] = [
+    [0x1, 0x2, 0x3, 0xC],
+    [0x4, 0x5, 0x6, 0xD],
+    [0x7, 0x8, 0x9, 0xE],
+    [0xA, 0x0, 0xB, 0xF],
+];
+
+// (row, column) in KEYPAD of every key held down.
+pub fn held_keypad_cells(keyboard: &[bool; 16]) -> Vec<(usize, usize)> {
+    let mut cells = Vec::new();
+    for (row, keys) in KEYPAD.iter().enumerate() {
+        for (column, &key) in keys.iter().enumerate() {
+            if keyboard[key as usize] {
+                cells.push((row, column));
+            }
+        }
+    }
+    return cells;
+}
+
+// The keypad as text with held keys in brackets, for learning which
+// key is which.
+pub fn keypad_overlay(keyboard: &[bool; 16]) -> Vec<String> {
+    let held = held_keypad_cells(keyboard);
+    return KEYPAD.iter().enumerate()
+        .map(|(row, keys)| keys.iter().enumerate()
+            .map(|(column, key)| if held.contains(&(row, column)) { format!("[{:X}]", key) } else { format!(" {:X} ", key) })
+            .collect::<String>())
+        .collect();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn pixel_turning_off_fades_for_a_frame() {
+        let mut phosphor = Phosphor::new(0.5);
+        assert_eq!(phosphor.update(&[true, false]), &[1.0, 0.0]);
+        assert_eq!(phosphor.update(&[false, false]), &[0.5, 0.0]);
+        assert_eq!(phosphor.update(&[false, false]), &[0.0, 0.0]);
+        assert_eq!(brightness_to_char(0.5), '+');
+    }
+
+    #[test]
+    fn full_fade_rate_disables_persistence() {
+        let mut phosphor = Phosphor::new(1.0);
+        phosphor.update(&[true]);
+        assert_eq!(phosphor.update(&[false]), &[0.0]);
+    }
+
+    #[test]
+    fn lowres_pixel_maps_to_its_hires_rectangle() {
+        assert_eq!(pixel_rect(LowresLayout::Scale, false, 3, 5), (6, 10, 2, 2));
+        assert_eq!(pixel_rect(LowresLayout::Center, false, 3, 5), (35, 21, 1, 1));
+        assert_eq!(pixel_rect(LowresLayout::Scale, true, 3, 5), (3, 5, 1, 1));
+    }
+
+    #[test]
+    fn fps_is_a_rolling_average() {
+        let mut fps = FpsCounter::new(2);
+        assert_eq!(fps.fps(), 0.0);
+        fps.record(Duration::from_millis(100));
+        assert!((fps.fps() - 10.0).abs() < 1e-3);
+        fps.record(Duration::from_millis(400));
+        assert!((fps.fps() - 4.0).abs() < 1e-3);
+        // The 100ms frame falls out of the window.
+        fps.record(Duration::from_millis(100));
+        assert!((fps.fps() - 4.0).abs() < 1e-3);
+        fps.record(Duration::from_millis(100));
+        assert!((fps.fps() - 10.0).abs() < 1e-3);
+    }
+
+    #[test]
+    fn speed_keys_step_by_a_tenth() {
+        assert_eq!(adjust_speed(100, ']'), 110);
+        assert_eq!(adjust_speed(100, '['), 90);
+        assert_eq!(adjust_speed(1, '['), 1);
+    }
+
+    #[test]
+    fn display_is_letterboxed_at_an_integer_scale() {
+        assert_eq!(fit_display((800, 600), (64, 32)), Viewport { scale : 12, x : 16, y : 108 });
+        assert_eq!(fit_display((800, 600), (128, 64)), Viewport { scale : 6, x : 16, y : 108 });
+        assert_eq!(fit_display((640, 320), (64, 32)), Viewport { scale : 10, x : 0, y : 0 });
+        assert_eq!(fit_display((32, 16), (64, 32)), Viewport { scale : 1, x : 0, y : 0 });
+    }
+
+    #[test]
+    fn composite_looks_up_the_plane_combination() {
+        let palette = Palette { colors : [0, 1, 2, 3] };
+        assert_eq!(composite(&[false, true, false, true], &[false, false, true, true], &palette), vec![0, 1, 2, 3]);
+        assert_eq!(composite(&[true], &[false], &Palette::default()), vec![0xFFFFCC00]);
+    }
+
+    #[test]
+    fn held_keys_highlight_their_keypad_cells() {
+        let mut keyboard = [false; 16];
+        keyboard[0x0] = true;
+        keyboard[0x5] = true;
+        keyboard[0xC] = true;
+        assert_eq!(held_keypad_cells(&keyboard), vec![(0, 3), (1, 1), (3, 1)]);
+        assert_eq!(keypad_overlay(&keyboard)[3], " A [0] B  F ");
+    }
+}
@@ -0,0 +1,146 @@
+use std::collections::BTreeMap;
+use std::fmt;
+
+use crate::{ MetaOpcode, RegularOpcode, supported_opcodes };
+
+// What a run exercised, to judge how well the emulator handles a rom.
+#[derive(Clone, Debug, Default)]
+pub struct Report {
+    pub cycles: u64,
+    pub unknown_opcodes: BTreeMap<u16, usize>,
+    // Calls into native routines by address, which point at roms
+    // written for a particular machine.
+    pub sys_calls: BTreeMap<u16, usize>,
+    // Executions per supported_opcodes pattern, e.g. "Dxyn".
+    pub opcode_counts: BTreeMap<&'static str, usize>,
+    pub max_stack_depth: u8,
+    pub sound_triggered: bool,
+    // Whether anything landed in the quirk log, so only ever set
+    // with `strict_quirks`.
+    pub quirk_sensitive: bool,
+}
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum StatsFormat {
+    Csv,
+    Json,
+}
+
+// The supported_opcodes pattern an opcode falls under, preferring the
+// one with the most literal digits, so 00E0 isn't counted as 0nnn.
+fn pattern(raw: u16) -> Option<&'static str> {
+    let literal = |p: &str| p.chars().filter(|c| c.is_ascii_hexdigit() && !c.is_ascii_lowercase()).count();
+    return supported_opcodes().into_iter()
+        .filter(|p| p.chars().enumerate().all(|(i, c)| match c.to_digit(16) {
+            Some(d) if !c.is_ascii_lowercase() => (raw >> (12 - 4 * i)) & 0xF == d as u16,
+            _ => true,
+        }))
+        .max_by_key(|p| literal(p));
+}
+
+impl Report {
+    pub(crate) fn record(&mut self, raw: u16, opcode: &MetaOpcode) {
+        self.cycles += 1;
+        if !matches!(opcode, MetaOpcode::Unknown(_)) {
+            if let Some(pattern) = pattern(raw) {
+                *self.opcode_counts.entry(pattern).or_insert(0) += 1;
+            }
+        }
+        match opcode {
+            MetaOpcode::Unknown(raw) => *self.unknown_opcodes.entry(*raw).or_insert(0) += 1,
+            MetaOpcode::Regular(RegularOpcode::SysCall { addr }) => *self.sys_calls.entry(*addr).or_insert(0) += 1,
+            _ => (),
+        }
+    }
+
+    pub(crate) fn observe(&mut self, sp: u8, sound_timer: u8) {
+        self.max_stack_depth = self.max_stack_depth.max(sp);
+        self.sound_triggered |= sound_timer > 0;
+    }
+
+    // The supported_opcodes patterns run at least once, in table order,
+    // to check which instructions a test rom actually exercises.
+    pub fn executed_opcodes(&self) -> Vec<&'static str> {
+        return supported_opcodes().into_iter().filter(|p| self.opcode_counts.contains_key(p)).collect();
+    }
+
+    // The instruction mix of the run, most frequent first.
+    pub fn export_stats(&self, format: StatsFormat) -> String {
+        let mut counts = self.opcode_counts.iter().collect::<Vec<_>>();
+        counts.sort_by(|a, b| b.1.cmp(a.1));
+        return match format {
+            StatsFormat::Csv => {
+                let rows = counts.iter().map(|(pattern, count)| format!("{},{}", pattern, count));
+                std::iter::once("opcode,count".to_string()).chain(rows).collect::<Vec<String>>().join("\n")
+            },
+            StatsFormat::Json => {
+                let fields = counts.iter()
+                    .map(|(pattern, count)| format!("\"{}\": {}", pattern, count))
+                    .collect::<Vec<String>>();
+                format!("{{{}}}", fields.join(", "))
+            },
+        };
+    }
+}
+
+impl fmt::Display for Report {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        let yes_no = |b: bool| if b {"yes"} else {"no"};
+        writeln!(f, "Cycles: {}", self.cycles)?;
+        if self.unknown_opcodes.is_empty() {
+            writeln!(f, "Unknown opcodes: none")?;
+        } else {
+            let counts = self.unknown_opcodes.iter()
+                .map(|(raw, count)| format!("{:04X} x{}", raw, count))
+                .collect::<Vec<String>>();
+            writeln!(f, "Unknown opcodes: {}", counts.join(", "))?;
+        }
+        if self.sys_calls.is_empty() {
+            writeln!(f, "SYS calls: none")?;
+        } else {
+            let counts = self.sys_calls.iter()
+                .map(|(addr, count)| format!("{:03X} x{}", addr, count))
+                .collect::<Vec<String>>();
+            writeln!(f, "SYS calls: {}", counts.join(", "))?;
+        }
+        let executed = self.executed_opcodes();
+        writeln!(f, "Opcodes executed: {} of {}: {}", executed.len(), supported_opcodes().len(), executed.join(", "))?;
+        writeln!(f, "Max stack depth: {}", self.max_stack_depth)?;
+        writeln!(f, "Sound: {}", yes_no(self.sound_triggered))?;
+        return write!(f, "Quirk-sensitive instructions: {}", yes_no(self.quirk_sensitive));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Chip8Builder;
+
+    fn two_opcode_run() -> Report {
+        let mut chip8 = Chip8Builder::new().build();
+        chip8.load_rom_bytes(&[0x60, 0x01, 0x70, 0x01, 0x70, 0x01]).unwrap();
+        for _ in 0..3 {
+            chip8.step().unwrap();
+        }
+        return chip8.report().clone();
+    }
+
+    #[test]
+    fn csv_lists_the_most_frequent_opcode_first() {
+        assert_eq!(two_opcode_run().export_stats(StatsFormat::Csv), "opcode,count\n7xkk,2\n6xkk,1");
+    }
+
+    #[test]
+    fn json_has_a_field_per_opcode() {
+        assert_eq!(two_opcode_run().export_stats(StatsFormat::Json), "{\"7xkk\": 2, \"6xkk\": 1}");
+    }
+
+    #[test]
+    fn sys_call_address_is_reported() {
+        assert!(matches!(crate::parse_opcode(0x0123), MetaOpcode::Regular(RegularOpcode::SysCall { addr : 0x123 })));
+        let mut chip8 = Chip8Builder::new().build();
+        chip8.load_rom_bytes(&[0x01, 0x23]).unwrap();
+        chip8.step().unwrap();
+        assert!(chip8.report().to_string().contains("SYS calls: 123 x1"));
+    }
+}
@@ -0,0 +1,87 @@
+use std::time::{ SystemTime, UNIX_EPOCH };
+
+// SplitMix64, the generator behind Cxkk.
+#[derive(Clone)]
+pub struct SplitMix64 {
+    state: u64,
+}
+
+impl SplitMix64 {
+    pub fn new(seed: u64) -> SplitMix64 {
+        return SplitMix64 { state : seed };
+    }
+
+    // Not much entropy, but enough to vary Cxkk between runs built
+    // without `rand`.
+    pub fn from_time() -> SplitMix64 {
+        let now = SystemTime::now().duration_since(UNIX_EPOCH).unwrap_or_default();
+        return SplitMix64::new(now.as_nanos() as u64);
+    }
+
+    pub fn next_u64(&mut self) -> u64 {
+        self.state = self.state.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let mut z = self.state;
+        z = (z ^ (z >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        z = (z ^ (z >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        return z ^ (z >> 31);
+    }
+
+    pub fn next_u8(&mut self) -> u8 {
+        return (self.next_u64() >> 56) as u8;
+    }
+}
+
+// Seeded runs always get this generator, so a seed gives the same Cxkk
+// sequence on every build and every version: replays and golden traces
+// like roms/maze.golden depend on it, so changing the generator breaks
+// them. rand's SmallRng makes no such promise, rand only seeds unseeded runs.
+pub(crate) type Chip8Rng = SplitMix64;
+
+#[cfg(feature = "rand")]
+pub(crate) fn new_rng(seed: Option<u64>) -> Chip8Rng {
+    return SplitMix64::new(seed.unwrap_or_else(rand::random));
+}
+
+#[cfg(not(feature = "rand"))]
+pub(crate) fn new_rng(seed: Option<u64>) -> Chip8Rng {
+    return match seed {
+        Some(seed) => SplitMix64::new(seed),
+        None => SplitMix64::from_time(),
+    };
+}
+
+pub(crate) fn random_byte(rng: &mut Chip8Rng) -> u8 {
+    return rng.next_u8();
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn known_sequence_for_seed_0() {
+        let mut rng = SplitMix64::new(0);
+        assert_eq!(rng.next_u64(), 0xE220_A839_7B1D_CDAF);
+        assert_eq!(rng.next_u64(), 0x6E78_9E6A_A1B9_65F4);
+        assert_eq!(rng.next_u64(), 0x06C4_5D18_8009_454F);
+    }
+
+    #[test]
+    fn bytes_are_roughly_uniform() {
+        let mut rng = SplitMix64::new(42);
+        let mut counts = [0u32; 256];
+        for _ in 0..256 * 1000 {
+            counts[rng.next_u8() as usize] += 1;
+        }
+        assert!(counts.iter().all(|&n| (800..1200).contains(&n)), "{:?}", counts);
+    }
+
+    // What Cxkk draws from a machine seeded with 0. Replays depend on
+    // these, so this test changing means shared replays break.
+    #[test]
+    fn pinned_bytes_for_seed_0() {
+        let mut rng = new_rng(Some(0));
+        let bytes = (0..16).map(|_| random_byte(&mut rng)).collect::<Vec<u8>>();
+        assert_eq!(bytes, [0xE2, 0x6E, 0x06, 0xF8, 0x1B, 0x53, 0x2C, 0xC5, 0x3E, 0xF3, 0x65, 0xC2, 0x86, 0x8E, 0xB5, 0x84]);
+    }
+}
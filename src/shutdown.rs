@@ -0,0 +1,134 @@
+use std::sync::atomic::{AtomicBool, Ordering};
+
+// Work that has to happen once when the emulator exits, whichever way
+// it exits: silencing the audio, flushing trace files. Backends register
+// their hooks and every exit route calls `run`, which is also done on
+// drop so an exit route that forgets still shuts down.
+pub struct Shutdown {
+    hooks: Vec<Box<dyn FnOnce()>>,
+}
+
+impl Shutdown {
+    pub fn new() -> Shutdown {
+        return Shutdown { hooks : Vec::new() };
+    }
+
+    pub fn on_shutdown(&mut self, hook: impl FnOnce() + 'static) {
+        self.hooks.push(Box::new(hook));
+    }
+
+    // Runs the hooks in the order they were registered. Later calls do
+    // nothing, they've already run.
+    pub fn run(&mut self) {
+        for hook in self.hooks.drain(..) {
+            hook();
+        }
+    }
+}
+
+impl Default for Shutdown {
+    fn default() -> Shutdown {
+        return Shutdown::new();
+    }
+}
+
+impl Drop for Shutdown {
+    fn drop(&mut self) {
+        self.run();
+    }
+}
+
+// Ctrl-C only sets a flag: the hooks aren't safe to run from a signal
+// handler, so the frame loops check `interrupted` and leave through the
+// usual exit route.
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+#[cfg(unix)]
+extern "C" fn on_interrupt(_signal: libc::c_int) {
+    INTERRUPTED.store(true, Ordering::SeqCst);
+}
+
+#[cfg(unix)]
+pub fn catch_interrupt() {
+    let handler = on_interrupt as extern "C" fn(libc::c_int);
+    unsafe {
+        libc::signal(libc::SIGINT, handler as libc::sighandler_t);
+    }
+}
+
+// Without signals Ctrl-C keeps killing the process outright.
+#[cfg(not(unix))]
+pub fn catch_interrupt() {}
+
+pub fn interrupted() -> bool {
+    return INTERRUPTED.load(Ordering::SeqCst);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::cell::{Cell, RefCell};
+    use std::panic::{self, AssertUnwindSafe};
+    use std::rc::Rc;
+
+    fn counted(shutdown: &mut Shutdown) -> Rc<Cell<u32>> {
+        let count = Rc::new(Cell::new(0));
+        let hook_count = Rc::clone(&count);
+        shutdown.on_shutdown(move || hook_count.set(hook_count.get() + 1));
+        return count;
+    }
+
+    #[test]
+    fn run_then_drop_fires_once() {
+        let mut shutdown = Shutdown::new();
+        let count = counted(&mut shutdown);
+        shutdown.run();
+        shutdown.run();
+        drop(shutdown);
+        assert_eq!(count.get(), 1);
+    }
+
+    #[test]
+    fn drop_alone_fires_once() {
+        let mut shutdown = Shutdown::new();
+        let count = counted(&mut shutdown);
+        drop(shutdown);
+        assert_eq!(count.get(), 1);
+    }
+
+    #[test]
+    fn panic_fires_once() {
+        let mut shutdown = Shutdown::new();
+        let count = counted(&mut shutdown);
+        let result = panic::catch_unwind(AssertUnwindSafe(move || {
+            let _shutdown = shutdown;
+            panic!("exit by panic");
+        }));
+        assert!(result.is_err());
+        assert_eq!(count.get(), 1);
+    }
+
+    #[test]
+    fn hooks_run_in_order() {
+        let order = Rc::new(RefCell::new(Vec::new()));
+        let mut shutdown = Shutdown::new();
+        for n in 0..3 {
+            let order = Rc::clone(&order);
+            shutdown.on_shutdown(move || order.borrow_mut().push(n));
+        }
+        shutdown.run();
+        assert_eq!(*order.borrow(), vec![0, 1, 2]);
+    }
+
+    // Nothing else in the library tests reads the flag, so raising the
+    // signal here can't stop another test's run.
+    #[cfg(unix)]
+    #[test]
+    fn ctrl_c_sets_the_flag_instead_of_killing() {
+        catch_interrupt();
+        unsafe {
+            libc::raise(libc::SIGINT);
+        }
+        assert!(interrupted());
+    }
+}
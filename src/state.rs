@@ -0,0 +1,203 @@
+use crate::{ Chip8, Chip8Error, KeyWait };
+
+// Save states hold the machine, not its configuration: quirks, load
+// address and the RNG stay with the instance a state is loaded into.
+const MAGIC: &[u8; 4] = b"C8S2";
+
+struct Reader<'a> {
+    bytes: &'a [u8],
+}
+
+impl<'a> Reader<'a> {
+    fn take(&mut self, n: usize) -> Result<&'a [u8], Chip8Error> {
+        if self.bytes.len() < n {
+            return Err(Chip8Error::InvalidState("The state is truncated."));
+        }
+        let (head, tail) = self.bytes.split_at(n);
+        self.bytes = tail;
+        return Ok(head);
+    }
+
+    fn u8(&mut self) -> Result<u8, Chip8Error> {
+        return Ok(self.take(1)?[0]);
+    }
+
+    fn u16(&mut self) -> Result<u16, Chip8Error> {
+        let b = self.take(2)?;
+        return Ok((b[0] as u16) << 8 | b[1] as u16);
+    }
+
+    fn u32(&mut self) -> Result<u32, Chip8Error> {
+        let b = self.take(4)?;
+        return Ok((b[0] as u32) << 24 | (b[1] as u32) << 16 | (b[2] as u32) << 8 | b[3] as u32);
+    }
+
+    fn bools(&mut self, out: &mut [bool]) -> Result<(), Chip8Error> {
+        let bytes = self.take(out.len())?;
+        for (o, &b) in out.iter_mut().zip(bytes) {
+            *o = b != 0;
+        }
+        return Ok(());
+    }
+}
+
+fn json_array(values: impl Iterator<Item = u16>) -> String {
+    return format!("[{}]", values.map(|n| n.to_string()).collect::<Vec<String>>().join(","));
+}
+
+impl Chip8 {
+    pub fn save_state(&self) -> Vec<u8> {
+        let mut out = MAGIC.to_vec();
+        out.extend_from_slice(&(self.memory.len() as u32).to_be_bytes());
+        out.extend_from_slice(&self.memory);
+        out.extend_from_slice(&self.program_counter.to_be_bytes());
+        out.extend_from_slice(&self.v);
+        out.extend_from_slice(&self.reg_i.to_be_bytes());
+        for addr in &self.stack {
+            out.extend_from_slice(&addr.to_be_bytes());
+        }
+        out.push(self.sp);
+        out.push(self.delay_timer);
+        out.push(self.sound_timer);
+        out.extend(self.keyboard.iter().map(|&k| k as u8));
+        out.push(self.key_wait.is_some() as u8);
+        let wait = self.key_wait.unwrap_or_default();
+        out.push(wait.pressed.unwrap_or(0xFF));
+        out.push(self.hires as u8);
+        out.extend(self.display_memory.iter().map(|&p| p as u8));
+        out.extend(self.plane2.iter().map(|&p| p as u8));
+        out.push(self.selected_planes);
+        out.extend_from_slice(&self.pattern_buffer);
+        out.push(self.pitch);
+        return out;
+    }
+
+    // The machine as JSON, for external tools to diff. Unlike save
+    // states this is one-way, there's nothing to load it back.
+    pub fn dump_state_json(&self) -> String {
+        let fields = vec![
+            format!("\"pc\": {}", self.program_counter),
+            format!("\"i\": {}", self.reg_i),
+            format!("\"sp\": {}", self.sp),
+            format!("\"v\": {}", json_array(self.v.iter().map(|&r| r as u16))),
+            format!("\"stack\": {}", json_array(self.stack().iter().cloned())),
+            format!("\"delay_timer\": {}", self.delay_timer),
+            format!("\"sound_timer\": {}", self.sound_timer),
+            format!("\"hires\": {}", self.hires),
+            format!("\"memory\": {}", json_array(self.memory.iter().map(|&b| b as u16))),
+            format!("\"display\": {}", json_array(self.display_memory.iter().map(|&p| p as u16))),
+        ];
+        return format!("{{{}}}", fields.join(", "));
+    }
+
+    // Overwrites this instance in place, reusing its buffers. Nothing is
+    // changed unless the whole state is valid.
+    pub fn load_state_into(&mut self, bytes: &[u8]) -> Result<(), Chip8Error> {
+        let mut r = Reader { bytes };
+        if r.take(4)? != MAGIC {
+            return Err(Chip8Error::InvalidState("Not a save state."));
+        }
+        let memory_len = r.u32()? as usize;
+        if memory_len != self.memory.len() {
+            return Err(Chip8Error::InvalidState("The state was saved with a different memory size."));
+        }
+        let memory = r.take(memory_len)?;
+        let program_counter = r.u16()?;
+        let v = r.take(16)?;
+        let reg_i = r.u16()?;
+        let mut stack = [0; 16];
+        for addr in stack.iter_mut() {
+            *addr = r.u16()?;
+        }
+        let sp = r.u8()?;
+        if sp as usize > stack.len() {
+            return Err(Chip8Error::InvalidState("The stack pointer is out of range."));
+        }
+        let delay_timer = r.u8()?;
+        let sound_timer = r.u8()?;
+        let mut keyboard = [false; 16];
+        r.bools(&mut keyboard)?;
+        let waiting_for_key = r.u8()? != 0;
+        let pressed = match r.u8()? {
+            0xFF => None,
+            key => Some(key & 0xF),
+        };
+        let hires = r.u8()? != 0;
+        let pixels = if hires { 128 * 64 } else { 64 * 32 };
+        let mut display_memory = vec![false; pixels];
+        r.bools(&mut display_memory)?;
+        let mut plane2 = vec![false; pixels];
+        r.bools(&mut plane2)?;
+        let selected_planes = r.u8()? & 3;
+        let mut pattern_buffer = [0; 16];
+        pattern_buffer.copy_from_slice(r.take(16)?);
+        let pitch = r.u8()?;
+
+        self.memory.copy_from_slice(memory);
+        self.program_counter = program_counter;
+        self.v.copy_from_slice(v);
+        self.store_i(reg_i);
+        self.stack = stack;
+        self.sp = sp;
+        self.delay_timer = delay_timer;
+        self.sound_timer = sound_timer;
+        self.keyboard = keyboard;
+        self.key_wait = if waiting_for_key { Some(KeyWait { pressed, cycles : 0, seen : self.key_events.len() }) } else { None };
+        self.hires = hires;
+        self.display_memory.clear();
+        self.display_memory.extend_from_slice(&display_memory);
+        self.plane2.clear();
+        self.plane2.extend_from_slice(&plane2);
+        self.selected_planes = selected_planes;
+        self.mark_all_dirty();
+        self.pattern_buffer = pattern_buffer;
+        self.pitch = pitch;
+        return Ok(());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::Chip8Builder;
+
+    #[test]
+    fn state_loads_into_a_different_instance() {
+        let mut saved = Chip8Builder::new().build();
+        saved.load_rom_bytes(&[0x60, 0x05, 0xA3, 0x00, 0x22, 0x00]).unwrap();
+        for _ in 0..3 {
+            saved.step().unwrap();
+        }
+        saved.delay_timer = 9;
+        let mut other = Chip8Builder::new().build();
+        other.load_rom_bytes(&[0x6F, 0x42, 0xD0, 0x05]).unwrap();
+        other.step().unwrap();
+        other.step().unwrap();
+        other.load_state_into(&saved.save_state()).unwrap();
+        assert_eq!(other.save_state(), saved.save_state());
+        assert_eq!((other.pc(), other.i(), other.reg(0), other.reg(0xF)), (0x200, 0x300, 5, 0));
+        assert_eq!(other.stack(), &[0x204]);
+        assert_eq!(other.frame_buffer(), saved.frame_buffer());
+    }
+
+    #[test]
+    fn a_bad_state_changes_nothing() {
+        let mut chip8 = Chip8Builder::new().build();
+        chip8.load_rom_bytes(&[0x60, 0x05]).unwrap();
+        let before = chip8.save_state();
+        assert!(chip8.load_state_into(&before[..before.len() - 1]).is_err());
+        assert_eq!(chip8.save_state(), before);
+    }
+
+    #[test]
+    fn json_has_the_pc_and_registers() {
+        let mut chip8 = Chip8Builder::new().build();
+        chip8.load_rom_bytes(&[0x60, 0x2A, 0x6F, 0x07, 0x12, 0x04]).unwrap();
+        for _ in 0..3 {
+            chip8.step().unwrap();
+        }
+        let json = chip8.dump_state_json();
+        assert!(json.starts_with("{\"pc\": 516, "), "{}", &json[..40]);
+        assert!(json.contains("\"v\": [42,0,0,0,0,0,0,0,0,0,0,0,0,0,0,7]"));
+        assert!(json.contains("\"stack\": []"));
+    }
+}
@@ -0,0 +1,128 @@
+use crate::{ Chip8, MetaOpcode, RegularOpcode, FlowControlOpcode, fetch_opcode, parse_opcode };
+
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum OpcodeCategory {
+    // Anything that changes the display or how it's drawn.
+    Draw,
+    // Jumps, calls and returns.
+    Jump,
+    Skip,
+    Arithmetic,
+    Load,
+    Other,
+}
+
+pub fn category(opcode: &MetaOpcode) -> OpcodeCategory {
+    use RegularOpcode::*;
+    return match opcode {
+        MetaOpcode::FlowControl(_) => OpcodeCategory::Jump,
+        MetaOpcode::Regular(opcode) => match opcode {
+            ClearScreen | ScrollDown { .. } | ScrollUp { .. } | ScrollRight | ScrollLeft
+            | LowRes | HighRes | SelectPlanes { .. } | DrawSprite { .. } => OpcodeCategory::Draw,
+            SkipIfRegValEqual { .. } | SkipIfRegValNotEqual { .. } | SkipIfRegRegEqual { .. }
+            | SkipIfRegRegNotEqual { .. } | SkipIfKeyPressed { .. } | SkipIfKeyNotPressed { .. } => OpcodeCategory::Skip,
+            SubRegFromReg { .. } | SubnRegFromReg { .. } | AddValToReg { .. } | AddRegToI { .. }
+            | OrRegReg { .. } | AndRegReg { .. } | XorRegReg { .. } | AddRegToReg { .. }
+            | ShiftRightReg { .. } | ShiftLeftReg { .. } => OpcodeCategory::Arithmetic,
+            SysCall { .. } | LoadPatternBuffer | LoadRegToPitch { .. } => OpcodeCategory::Other,
+            _ => OpcodeCategory::Load,
+        },
+        MetaOpcode::Unknown(_) => OpcodeCategory::Other,
+    };
+}
+
+// The V registers an instruction reads or writes. VF only counts where
+// it's always written, not where a quirk makes it so.
+pub fn touched_registers(opcode: &MetaOpcode) -> Vec<u8> {
+    use RegularOpcode::*;
+    return match opcode {
+        MetaOpcode::FlowControl(FlowControlOpcode::JumpPlusV0 { .. }) => vec![0],
+        MetaOpcode::Regular(opcode) => match *opcode {
+            SkipIfRegValEqual { x, .. } | SkipIfRegValNotEqual { x, .. } | SkipIfKeyPressed { x }
+            | SkipIfKeyNotPressed { x } | LoadValToReg { x, .. } | LoadDelayTimerToReg { x }
+            | LoadKeyToReg { x } | LoadRegToDelayTimer { x } | LoadRegToSoundTimer { x }
+            | LoadSpriteLocationToI { x } | LoadBigSpriteLocationToI { x } | LoadRegBcdToMem { x }
+            | LoadRandomAndValToReg { x, .. } | LoadRegToPitch { x } | AddValToReg { x, .. }
+            | AddRegToI { x } => vec![x],
+            SkipIfRegRegEqual { x, y } | SkipIfRegRegNotEqual { x, y } | LoadRegToReg { x, y }
+            | OrRegReg { x, y } | AndRegReg { x, y } | XorRegReg { x, y } => vec![x, y],
+            SubRegFromReg { x, y } | SubnRegFromReg { x, y } | AddRegToReg { x, y }
+            | ShiftRightReg { x, y } | ShiftLeftReg { x, y } | DrawSprite { x, y, .. } => vec![x, y, 0xF],
+            LoadRegsToMem { n } | LoadMemToRegs { n } => (0..=n).collect(),
+            _ => Vec::new(),
+        },
+        _ => Vec::new(),
+    };
+}
+
+// Which instructions make it into a trace, judged on the one about to
+// execute so a line describes the state it ran from.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum TraceFilter {
+    Category(OpcodeCategory),
+    // Inclusive on both ends.
+    PcRange { start: u16, end: u16 },
+    TouchesReg(u8),
+}
+
+impl TraceFilter {
+    // draws, jumps, skips, arithmetic, loads, pc=200-2FF or v3.
+    pub fn from_name(name: &str) -> Option<TraceFilter> {
+        let hex = |s: &str| u16::from_str_radix(s, 16).ok();
+        return match name.to_lowercase().as_str() {
+            "draws" => Some(TraceFilter::Category(OpcodeCategory::Draw)),
+            "jumps" => Some(TraceFilter::Category(OpcodeCategory::Jump)),
+            "skips" => Some(TraceFilter::Category(OpcodeCategory::Skip)),
+            "arithmetic" => Some(TraceFilter::Category(OpcodeCategory::Arithmetic)),
+            "loads" => Some(TraceFilter::Category(OpcodeCategory::Load)),
+            name if name.starts_with("pc=") => {
+                let mut bounds = name[3..].splitn(2, '-');
+                let start = hex(bounds.next()?)?;
+                let end = bounds.next().map_or(Some(start), hex)?;
+                Some(TraceFilter::PcRange { start, end })
+            },
+            name if name.len() == 2 && name.starts_with('v') => {
+                u8::from_str_radix(&name[1..], 16).ok().map(TraceFilter::TouchesReg)
+            },
+            _ => None,
+        };
+    }
+
+    pub fn matches(&self, chip8: &Chip8) -> bool {
+        let opcode = parse_opcode(fetch_opcode(chip8));
+        return match *self {
+            TraceFilter::Category(wanted) => category(&opcode) == wanted,
+            TraceFilter::PcRange { start, end } => (start..=end).contains(&chip8.program_counter),
+            TraceFilter::TouchesReg(x) => touched_registers(&opcode).contains(&x),
+        };
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::Chip8Builder;
+
+    #[test]
+    fn draws_filter_traces_only_the_draw() {
+        let mut chip8 = Chip8Builder::new().build();
+        chip8.load_rom_bytes(&[0x60, 0x01, 0x61, 0x02, 0x62, 0x03, 0xD0, 0x15, 0x63, 0x04, 0x64, 0x05]).unwrap();
+        let filter = TraceFilter::from_name("draws").unwrap();
+        let mut lines = Vec::new();
+        for _ in 0..6 {
+            if filter.matches(&chip8) {
+                lines.push(chip8.trace_line());
+            }
+            chip8.step().unwrap();
+        }
+        assert_eq!(lines.len(), 1);
+        assert!(lines[0].starts_with("PC:0x0206 OP:D015"), "{}", lines[0]);
+    }
+
+    #[test]
+    fn filter_names() {
+        assert_eq!(TraceFilter::from_name("pc=200-2ff"), Some(TraceFilter::PcRange { start : 0x200, end : 0x2FF }));
+        assert_eq!(TraceFilter::from_name("vA"), Some(TraceFilter::TouchesReg(0xA)));
+        assert_eq!(TraceFilter::from_name("everything"), None);
+    }
+}
@@ -0,0 +1,90 @@
+use std::fmt;
+
+use crate::{ MetaOpcode, RegularOpcode, FlowControlOpcode, parse_opcode };
+
+// What a rom looks like without running it, to triage one that won't.
+// The rom is decoded as if every pair of bytes were an instruction, so
+// sprite and other data can show up as unknown opcodes too.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Validation {
+    pub size: usize,
+    pub unknown_opcodes: usize,
+    pub uses_schip: bool,
+    pub uses_xochip: bool,
+    // (address, target) of every jump or call leaving the rom.
+    pub outside_jumps: Vec<(u16, u16)>,
+}
+
+pub fn validate_rom(rom: &[u8], load_address: u16) -> Validation {
+    let end = load_address as usize + rom.len();
+    let mut validation = Validation { size : rom.len(), .. Validation::default() };
+    for (i, pair) in rom.chunks(2).enumerate() {
+        let addr = load_address.wrapping_add(2 * i as u16);
+        let raw = (pair[0] as u16) << 8 | pair.get(1).cloned().unwrap_or(0) as u16;
+        match parse_opcode(raw) {
+            MetaOpcode::Unknown(_) => validation.unknown_opcodes += 1,
+            MetaOpcode::FlowControl(FlowControlOpcode::Jump { addr : target })
+            | MetaOpcode::FlowControl(FlowControlOpcode::JumpPlusV0 { addr : target })
+            | MetaOpcode::FlowControl(FlowControlOpcode::Call { addr : target })
+                if target < load_address || target as usize >= end => validation.outside_jumps.push((addr, target)),
+            MetaOpcode::Regular(RegularOpcode::ScrollDown { .. })
+            | MetaOpcode::Regular(RegularOpcode::ScrollRight)
+            | MetaOpcode::Regular(RegularOpcode::ScrollLeft)
+            | MetaOpcode::Regular(RegularOpcode::LowRes)
+            | MetaOpcode::Regular(RegularOpcode::HighRes)
+            | MetaOpcode::Regular(RegularOpcode::LoadBigSpriteLocationToI { .. })
+            | MetaOpcode::Regular(RegularOpcode::DrawSprite { n : 0, .. }) => validation.uses_schip = true,
+            MetaOpcode::Regular(RegularOpcode::ScrollUp { .. })
+            | MetaOpcode::Regular(RegularOpcode::SelectPlanes { .. })
+            | MetaOpcode::Regular(RegularOpcode::LoadPatternBuffer)
+            | MetaOpcode::Regular(RegularOpcode::LoadRegToPitch { .. }) => validation.uses_xochip = true,
+            _ => (),
+        }
+    }
+    return validation;
+}
+
+impl fmt::Display for Validation {
+    fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
+        writeln!(f, "Size: {} bytes", self.size)?;
+        writeln!(f, "Unknown opcodes: {}", self.unknown_opcodes)?;
+        let mode = match (self.uses_schip, self.uses_xochip) {
+            (_, true) => "XO-CHIP (--xochip)",
+            (true, false) => "SUPER-CHIP (--schip)",
+            (false, false) => "CHIP-8",
+        };
+        writeln!(f, "Mode: {}", mode)?;
+        if self.outside_jumps.is_empty() {
+            return write!(f, "Jumps outside the rom: none");
+        }
+        let jumps = self.outside_jumps.iter()
+            .map(|(addr, target)| format!("{:03X} -> {:03X}", addr, target))
+            .collect::<Vec<String>>();
+        return write!(f, "Jumps outside the rom: {}", jumps.join(", "));
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn an_schip_opcode_is_flagged() {
+        let validation = validate_rom(&[0x00, 0xFF, 0xF0, 0xFF, 0x13, 0x00, 0x12, 0x06], 0x200);
+        assert_eq!(validation, Validation {
+            size : 8,
+            unknown_opcodes : 1,
+            uses_schip : true,
+            uses_xochip : false,
+            outside_jumps : vec![(0x204, 0x300)],
+        });
+        assert!(validation.to_string().contains("Mode: SUPER-CHIP (--schip)"));
+    }
+
+    #[test]
+    fn a_plain_rom_needs_no_extension() {
+        let validation = validate_rom(&[0x60, 0x01, 0x12, 0x02], 0x200);
+        assert!(!validation.uses_schip && !validation.uses_xochip);
+        assert!(validation.outside_jumps.is_empty());
+    }
+}
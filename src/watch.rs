@@ -0,0 +1,119 @@
+use std::time::{ Duration, Instant };
+
+pub trait ChangeSource {
+    // Returns true if the file changed since the last call.
+    fn changed(&mut self) -> bool;
+}
+
+// Editors often write a file several times per save, so a reload is
+// only requested once the file has been quiet for `debounce`.
+pub struct ReloadTrigger {
+    debounce: Duration,
+    last_change: Option<Instant>,
+}
+
+impl ReloadTrigger {
+    pub fn new(debounce: Duration) -> ReloadTrigger {
+        return ReloadTrigger { debounce, last_change : None };
+    }
+
+    pub fn poll(&mut self, source: &mut impl ChangeSource, now: Instant) -> bool {
+        if source.changed() {
+            self.last_change = Some(now);
+            return false;
+        }
+        return match self.last_change {
+            Some(t) if now.duration_since(t) >= self.debounce => {
+                self.last_change = None;
+                true
+            },
+            _ => false,
+        };
+    }
+}
+
+#[cfg(feature = "watch")]
+pub use self::notify_watcher::NotifyWatcher;
+
+#[cfg(feature = "watch")]
+mod notify_watcher {
+    use std::ffi::OsString;
+    use std::path::Path;
+    use std::sync::mpsc::{ channel, Receiver };
+
+    use notify::{ Event, RecommendedWatcher, RecursiveMode, Watcher };
+
+    use super::ChangeSource;
+
+    pub struct NotifyWatcher {
+        _watcher: RecommendedWatcher,
+        events: Receiver<notify::Result<Event>>,
+        file_name: OsString,
+    }
+
+    impl NotifyWatcher {
+        // Watches the parent directory, since editors that save by
+        // renaming a temporary file would detach a watch on the file itself.
+        pub fn new(filename: &str) -> notify::Result<NotifyWatcher> {
+            let path = Path::new(filename);
+            let dir = match path.parent() {
+                Some(p) if !p.as_os_str().is_empty() => p,
+                _ => Path::new("."),
+            };
+            let (tx, events) = channel();
+            let mut watcher = notify::recommended_watcher(tx)?;
+            watcher.watch(dir, RecursiveMode::NonRecursive)?;
+            let file_name = path.file_name().map(|n| n.to_os_string()).unwrap_or_default();
+            return Ok(NotifyWatcher { _watcher : watcher, events, file_name });
+        }
+    }
+
+    impl ChangeSource for NotifyWatcher {
+        fn changed(&mut self) -> bool {
+            let mut changed = false;
+            while let Ok(event) = self.events.try_recv() {
+                if let Ok(event) = event {
+                    changed |= (event.kind.is_modify() || event.kind.is_create())
+                        && event.paths.iter().any(|p| p.file_name() == Some(self.file_name.as_os_str()));
+                }
+            }
+            return changed;
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Reports a change on the calls listed in `changes`.
+    struct MockWatcher {
+        changes: Vec<bool>,
+    }
+
+    impl ChangeSource for MockWatcher {
+        fn changed(&mut self) -> bool {
+            return if self.changes.is_empty() { false } else { self.changes.remove(0) };
+        }
+    }
+
+    #[test]
+    fn reload_once_the_file_is_quiet() {
+        let debounce = Duration::from_millis(100);
+        let mut trigger = ReloadTrigger::new(debounce);
+        let mut watcher = MockWatcher { changes : vec![true, true] };
+        let start = Instant::now();
+        assert!(!trigger.poll(&mut watcher, start));
+        assert!(!trigger.poll(&mut watcher, start + Duration::from_millis(50)));
+        assert!(!trigger.poll(&mut watcher, start + Duration::from_millis(100)));
+        assert!(trigger.poll(&mut watcher, start + Duration::from_millis(150)));
+        assert!(!trigger.poll(&mut watcher, start + Duration::from_millis(300)));
+    }
+
+    #[test]
+    fn no_reload_without_a_change() {
+        let mut trigger = ReloadTrigger::new(Duration::from_millis(100));
+        let mut watcher = MockWatcher { changes : vec![] };
+        assert!(!trigger.poll(&mut watcher, Instant::now() + Duration::from_secs(1)));
+    }
+}